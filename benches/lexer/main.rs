@@ -0,0 +1,57 @@
+// Compares the byte-oriented `Lexer` with the chars-based `tokenize` it replaced, on a
+// generated source of about 8 MB. Run it with `cargo bench --bench lexer`.
+//
+// The old tokenizer also converted literals to their values, which the parser now does,
+// so the comparison covers everything that happened to the text before preprocessing.
+mod old_lexer;
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use rust_9cc::lexer::Lexer;
+
+const SOURCE_SIZE: usize = 8 << 20;
+const RUNS: usize = 5;
+
+// Lines with the mix of identifiers, keywords, literals, punctuators and comments that
+// typical code has.
+fn generate_source() -> String {
+    let mut source = String::with_capacity(SOURCE_SIZE + 256);
+    let mut i = 0;
+    while source.len() < SOURCE_SIZE {
+        source.push_str(&format!(
+            "/* block {i} */\n\
+             static const long value_{i} = {i} * 0x1f + 'a'; // line comment\n\
+             if (value_{i} >= 10 && value_{i} != 0) {{ s = \"text\\n\"; x += value_{i} << 2; }}\n"
+        ));
+        i += 1;
+    }
+    source
+}
+
+// Runs `f` several times and returns the fastest time with the number of tokens.
+fn measure(f: impl Fn() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut tokens = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        tokens = black_box(f());
+        best = best.min(start.elapsed());
+    }
+    (best, tokens)
+}
+
+fn main() {
+    let source = generate_source();
+    println!(
+        "source: {:.1} MB, best of {} runs",
+        source.len() as f64 / (1 << 20) as f64,
+        RUNS
+    );
+    let (old, tokens) = measure(|| old_lexer::tokenize(black_box(&source)).len());
+    println!("old tokenize: {:>8.1?} ({} tokens)", old, tokens);
+    let (new, tokens) = measure(|| Lexer::new(black_box(&source)).count());
+    println!("Lexer:        {:>8.1?} ({} tokens)", new, tokens);
+}
@@ -0,0 +1,660 @@
+// The chars-based `tokenize` that `Lexer` replaced, kept as it was so that
+// benches/lexer.rs can compare the two. It is not used by the compiler.
+#![allow(dead_code, clippy::upper_case_acronyms)]
+
+use std::{fmt, iter::Peekable, str::Chars};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Token {
+    Identifier(String),
+    Number(i64, IntegerType),
+    String(Vec<u8>),
+    Keyword(Keyword),
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Period,
+    Arrow,
+    Increment,
+    Decrement,
+    Ampersand,
+    Asterisk,
+    Plus,
+    Minus,
+    Tilde,
+    Exclamation,
+    Slash,
+    Percent,
+    LeftShift,
+    RightShift,
+    LessThan,
+    GreaterThan,
+    LessThanOrEqual,
+    GreaterThanOrEqual,
+    Equal,
+    NotEqual,
+    Caret,
+    Pipe,
+    LogicalAnd,
+    LogicalOr,
+    Question,
+    Colon,
+    SemiColon,
+    Ellipsis,
+    Assign,
+    MultiplyAssign,
+    DivideAssign,
+    ModuloAssign,
+    AddAssign,
+    SubtractAssign,
+    LeftShiftAssign,
+    RightShiftAssign,
+    AndAssign,
+    XorAssign,
+    OrAssign,
+    Comma,
+    Hash,
+    HashHash,
+    // Only seen by the preprocessor, which needs line boundaries for directives and
+    // whitespace for function-like macro definitions and stringification.
+    Newline,
+    Space,
+    EOF,
+}
+
+// The type of an integer constant, deduced from its magnitude and suffix.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IntegerType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Keyword {
+    Auto,
+    Break,
+    Case,
+    Char,
+    Const,
+    Continue,
+    Default,
+    Do,
+    Double,
+    Else,
+    Enum,
+    Extern,
+    Float,
+    For,
+    Goto,
+    If,
+    Inline,
+    Int,
+    Long,
+    Register,
+    Restrict,
+    Return,
+    Short,
+    Signed,
+    Sizeof,
+    Static,
+    Struct,
+    Switch,
+    Typedef,
+    Union,
+    Unsigned,
+    Void,
+    Volatile,
+    While,
+    Alignas,
+    Alignof,
+    Atomic,
+    Bool,
+    Complex,
+    Generic,
+    Imaginary,
+    Noreturn,
+    StaticAssert,
+    ThreadLocal,
+}
+
+// The keywords of C11 6.4.1.
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("auto", Keyword::Auto),
+    ("break", Keyword::Break),
+    ("case", Keyword::Case),
+    ("char", Keyword::Char),
+    ("const", Keyword::Const),
+    ("continue", Keyword::Continue),
+    ("default", Keyword::Default),
+    ("do", Keyword::Do),
+    ("double", Keyword::Double),
+    ("else", Keyword::Else),
+    ("enum", Keyword::Enum),
+    ("extern", Keyword::Extern),
+    ("float", Keyword::Float),
+    ("for", Keyword::For),
+    ("goto", Keyword::Goto),
+    ("if", Keyword::If),
+    ("inline", Keyword::Inline),
+    ("int", Keyword::Int),
+    ("long", Keyword::Long),
+    ("register", Keyword::Register),
+    ("restrict", Keyword::Restrict),
+    ("return", Keyword::Return),
+    ("short", Keyword::Short),
+    ("signed", Keyword::Signed),
+    ("sizeof", Keyword::Sizeof),
+    ("static", Keyword::Static),
+    ("struct", Keyword::Struct),
+    ("switch", Keyword::Switch),
+    ("typedef", Keyword::Typedef),
+    ("union", Keyword::Union),
+    ("unsigned", Keyword::Unsigned),
+    ("void", Keyword::Void),
+    ("volatile", Keyword::Volatile),
+    ("while", Keyword::While),
+    ("_Alignas", Keyword::Alignas),
+    ("_Alignof", Keyword::Alignof),
+    ("_Atomic", Keyword::Atomic),
+    ("_Bool", Keyword::Bool),
+    ("_Complex", Keyword::Complex),
+    ("_Generic", Keyword::Generic),
+    ("_Imaginary", Keyword::Imaginary),
+    ("_Noreturn", Keyword::Noreturn),
+    ("_Static_assert", Keyword::StaticAssert),
+    ("_Thread_local", Keyword::ThreadLocal),
+];
+
+// The punctuators of C11 6.4.6, in their canonical spelling.
+const PUNCTUATORS: &[(&str, Token)] = &[
+    ("[", Token::LeftBracket),
+    ("]", Token::RightBracket),
+    ("(", Token::LeftParen),
+    (")", Token::RightParen),
+    ("{", Token::LeftBrace),
+    ("}", Token::RightBrace),
+    (".", Token::Period),
+    ("->", Token::Arrow),
+    ("++", Token::Increment),
+    ("--", Token::Decrement),
+    ("&", Token::Ampersand),
+    ("*", Token::Asterisk),
+    ("+", Token::Plus),
+    ("-", Token::Minus),
+    ("~", Token::Tilde),
+    ("!", Token::Exclamation),
+    ("/", Token::Slash),
+    ("%", Token::Percent),
+    ("<<", Token::LeftShift),
+    (">>", Token::RightShift),
+    ("<", Token::LessThan),
+    (">", Token::GreaterThan),
+    ("<=", Token::LessThanOrEqual),
+    (">=", Token::GreaterThanOrEqual),
+    ("==", Token::Equal),
+    ("!=", Token::NotEqual),
+    ("^", Token::Caret),
+    ("|", Token::Pipe),
+    ("&&", Token::LogicalAnd),
+    ("||", Token::LogicalOr),
+    ("?", Token::Question),
+    (":", Token::Colon),
+    (";", Token::SemiColon),
+    ("...", Token::Ellipsis),
+    ("=", Token::Assign),
+    ("*=", Token::MultiplyAssign),
+    ("/=", Token::DivideAssign),
+    ("%=", Token::ModuloAssign),
+    ("+=", Token::AddAssign),
+    ("-=", Token::SubtractAssign),
+    ("<<=", Token::LeftShiftAssign),
+    (">>=", Token::RightShiftAssign),
+    ("&=", Token::AndAssign),
+    ("^=", Token::XorAssign),
+    ("|=", Token::OrAssign),
+    (",", Token::Comma),
+    ("#", Token::Hash),
+    ("##", Token::HashHash),
+];
+
+// Alternative spellings of punctuators. They lex like the originals but are never printed.
+const DIGRAPHS: &[(&str, Token)] = &[
+    ("<:", Token::LeftBracket),
+    (":>", Token::RightBracket),
+    ("<%", Token::LeftBrace),
+    ("%>", Token::RightBrace),
+    ("%:", Token::Hash),
+    ("%:%:", Token::HashHash),
+];
+
+impl fmt::Display for Token {
+    // Spells the token as source text. Number tokens are spelled from their value and
+    // type, so `0x1F` and `'a'` come out as `31` and `97`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Identifier(v) => write!(f, "{}", v),
+            Token::Number(v, ty) => match ty {
+                IntegerType::Int => write!(f, "{}", v),
+                IntegerType::UnsignedInt => write!(f, "{}u", *v as u64),
+                IntegerType::Long => write!(f, "{}l", v),
+                IntegerType::UnsignedLong => write!(f, "{}ul", *v as u64),
+                IntegerType::LongLong => write!(f, "{}ll", v),
+                IntegerType::UnsignedLongLong => write!(f, "{}ull", *v as u64),
+            },
+            Token::String(v) => {
+                write!(f, "\"")?;
+                for &b in v {
+                    match b {
+                        b'"' | b'\\' => write!(f, "\\{}", b as char)?,
+                        b'\n' => write!(f, "\\n")?,
+                        b'\t' => write!(f, "\\t")?,
+                        0x20..=0x7e => write!(f, "{}", b as char)?,
+                        _ => write!(f, "\\{:03o}", b)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Token::Keyword(v) => write!(f, "{}", v),
+            Token::Newline => writeln!(f),
+            Token::Space => write!(f, " "),
+            Token::EOF => Ok(()),
+            punctuator => match PUNCTUATORS.iter().find(|(_, t)| t == punctuator) {
+                Some((spelling, _)) => write!(f, "{}", spelling),
+                None => unreachable!("{:?} has no spelling", punctuator),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match KEYWORDS.iter().find(|(_, k)| k == self) {
+            Some((spelling, _)) => write!(f, "{}", spelling),
+            None => unreachable!("{:?} has no spelling", self),
+        }
+    }
+}
+
+pub fn tokenize(s: &str) -> Vec<Token> {
+    let s = &splice_lines(s);
+    let mut tokens = Vec::new();
+    let mut iter = s.chars().peekable();
+    // Line breaks inside block comments. They cannot end the current line, which matters
+    // to directives, so they are emitted at the next real line break to keep line counts.
+    let mut pending_newlines = 0;
+    loop {
+        match iter.peek().copied() {
+            Some('\n') => {
+                for _ in 0..=pending_newlines {
+                    tokens.push(Token::Newline);
+                }
+                pending_newlines = 0;
+                iter.next();
+            }
+            Some(c) if c.is_whitespace() => {
+                push_space(&mut tokens);
+                iter.next();
+            }
+            Some(c) if '_' == c || c.is_alphabetic() => {
+                let mut ret = String::new();
+                loop {
+                    match iter.peek() {
+                        Some(&cc) if '_' == cc || cc.is_ascii_digit() || cc.is_alphabetic() => {
+                            ret.push(cc);
+                            iter.next();
+                        }
+                        _ => {
+                            break;
+                        }
+                    }
+                }
+                match KEYWORDS.iter().find(|(k, _)| *k == ret) {
+                    Some((_, keyword)) => tokens.push(Token::Keyword(*keyword)),
+                    None => tokens.push(Token::Identifier(ret)),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut ret = String::new();
+                loop {
+                    match iter.peek().copied() {
+                        Some(cc) if '_' == cc || cc.is_ascii_alphanumeric() => {
+                            ret.push(cc);
+                            iter.next();
+                        }
+                        // A digit separator is only part of the number when a digit follows it.
+                        Some('\'')
+                            if iter
+                                .clone()
+                                .nth(1)
+                                .is_some_and(|cc| cc.is_ascii_alphanumeric()) =>
+                        {
+                            ret.push('\'');
+                            iter.next();
+                        }
+                        _ => {
+                            break;
+                        }
+                    }
+                }
+                let (v, ty) = convert_integer_literal(&ret);
+                tokens.push(Token::Number(v, ty));
+            }
+            Some('\'') => {
+                iter.next();
+                tokens.push(Token::Number(
+                    read_char_literal(&mut iter),
+                    IntegerType::Int,
+                ));
+            }
+            Some('"') => {
+                iter.next();
+                tokens.push(Token::String(read_string_literal(&mut iter)));
+            }
+            Some('/') if matches!(iter.clone().nth(1), Some('/' | '*')) => {
+                let start = iter.clone();
+                iter.next();
+                if iter.next_if_eq(&'/').is_some() {
+                    push_space(&mut tokens);
+                    while iter.next_if(|&cc| cc != '\n').is_some() {}
+                } else {
+                    push_space(&mut tokens);
+                    iter.next();
+                    let mut prev = None;
+                    loop {
+                        match iter.next() {
+                            Some('/') if prev == Some('*') => break,
+                            Some(cc) => {
+                                if cc == '\n' {
+                                    pending_newlines += 1;
+                                }
+                                prev = Some(cc);
+                            }
+                            None => {
+                                let pos = s.chars().count() - start.count();
+                                error_at(s, pos, "unterminated block comment");
+                            }
+                        }
+                    }
+                }
+            }
+            Some(c) => match punctuator(&iter) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    iter.nth(len - 1);
+                }
+                None => panic!("unexpected character: {}", c),
+            },
+            None => {
+                tokens.push(Token::EOF);
+                break;
+            }
+        }
+    }
+    tokens
+}
+
+// Finds the longest punctuator at the start of `iter`, returning it with its length.
+fn punctuator(iter: &Peekable<Chars>) -> Option<(Token, usize)> {
+    PUNCTUATORS
+        .iter()
+        .chain(DIGRAPHS)
+        .filter(|(spelling, _)| {
+            let mut ahead = iter.clone();
+            spelling.chars().all(|c| ahead.next() == Some(c))
+        })
+        .max_by_key(|(spelling, _)| spelling.len())
+        .map(|(spelling, token)| (token.clone(), spelling.len()))
+}
+
+// Deletes each backslash-newline pair, joining physical lines into logical ones
+// (translation phase 2). The deleted line breaks are put back after the logical line
+// so that later lines keep their line numbers.
+fn splice_lines(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut spliced = 0;
+    let mut iter = s.chars().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' if iter.next_if_eq(&'\n').is_some() => spliced += 1,
+            '\n' => {
+                ret.push('\n');
+                ret.extend(std::iter::repeat_n('\n', spliced));
+                spliced = 0;
+            }
+            _ => ret.push(c),
+        }
+    }
+    ret.extend(std::iter::repeat_n('\n', spliced));
+    ret
+}
+
+// Replaces the nine trigraph sequences such as `??=` with the characters they stand for
+// (translation phase 1). Trigraphs are rare and surprising, so callers opt into this.
+pub fn replace_trigraphs(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("??") {
+        let replacement = match rest[i + 2..].chars().next() {
+            Some('=') => '#',
+            Some('(') => '[',
+            Some('/') => '\\',
+            Some(')') => ']',
+            Some('\'') => '^',
+            Some('<') => '{',
+            Some('!') => '|',
+            Some('>') => '}',
+            Some('-') => '~',
+            _ => {
+                ret.push_str(&rest[..i + 1]);
+                rest = &rest[i + 1..];
+                continue;
+            }
+        };
+        ret.push_str(&rest[..i]);
+        ret.push(replacement);
+        rest = &rest[i + 3..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+// A run of whitespace and comments is a single separator.
+fn push_space(tokens: &mut Vec<Token>) {
+    if tokens.last() != Some(&Token::Space) {
+        tokens.push(Token::Space);
+    }
+}
+
+// Reports an error at the `pos`-th character of `s`, showing the offending line.
+fn error_at(s: &str, pos: usize, message: &str) -> ! {
+    let offset = s.char_indices().nth(pos).map_or(s.len(), |(i, _)| i);
+    let line_start = s[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = s[line_start..].lines().next().unwrap_or("");
+    let line_number = s[..line_start].matches('\n').count() + 1;
+    let column = s[line_start..offset].chars().count();
+    panic!(
+        "{}:{}: {}\n{}\n{}^",
+        line_number,
+        column + 1,
+        message,
+        line,
+        " ".repeat(column)
+    );
+}
+
+// Converts an integer literal such as `0x1fUL` or `1'000` into its value and type,
+// following the rules of C11 6.4.4.1 for an LP64 target. The value keeps the bit pattern
+// of unsigned constants that do not fit into an i64.
+fn convert_integer_literal(s: &str) -> (i64, IntegerType) {
+    let lower = s.to_ascii_lowercase();
+    let (radix, body) = if lower.starts_with("0x") {
+        (16, &s[2..])
+    } else if lower.starts_with("0b") {
+        (2, &s[2..])
+    } else if s.starts_with('0') && s.len() > 1 {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    let suffix_start = body
+        .find(|c: char| c != '\'' && !c.is_digit(radix))
+        .unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(suffix_start);
+    if digits.is_empty() && radix != 8 {
+        panic!("invalid integer literal: {}", s);
+    }
+    if digits.starts_with('\'') || digits.ends_with('\'') || digits.contains("''") {
+        panic!("invalid digit separator in integer literal: {}", s);
+    }
+    let (unsigned, long) = match suffix {
+        "" => (false, 0),
+        "u" | "U" => (true, 0),
+        "l" | "L" => (false, 1),
+        "ll" | "LL" => (false, 2),
+        "ul" | "uL" | "Ul" | "UL" | "lu" | "lU" | "Lu" | "LU" => (true, 1),
+        "ull" | "uLL" | "Ull" | "ULL" | "llu" | "llU" | "LLu" | "LLU" => (true, 2),
+        _ => panic!("invalid suffix \"{}\" on integer literal: {}", suffix, s),
+    };
+    let digits = digits.replace('\'', "");
+    let v = if digits.is_empty() {
+        0
+    } else {
+        match u64::from_str_radix(&digits, radix) {
+            Ok(v) => v,
+            Err(_) => panic!("integer literal is too large: {}", s),
+        }
+    };
+    let candidates: &[IntegerType] = match (unsigned, long, radix == 10) {
+        (false, 0, true) => &[IntegerType::Int, IntegerType::Long, IntegerType::LongLong],
+        (false, 0, false) => &[
+            IntegerType::Int,
+            IntegerType::UnsignedInt,
+            IntegerType::Long,
+            IntegerType::UnsignedLong,
+            IntegerType::LongLong,
+            IntegerType::UnsignedLongLong,
+        ],
+        (true, 0, _) => &[
+            IntegerType::UnsignedInt,
+            IntegerType::UnsignedLong,
+            IntegerType::UnsignedLongLong,
+        ],
+        (false, 1, true) => &[IntegerType::Long, IntegerType::LongLong],
+        (false, 1, false) => &[
+            IntegerType::Long,
+            IntegerType::UnsignedLong,
+            IntegerType::LongLong,
+            IntegerType::UnsignedLongLong,
+        ],
+        (true, 1, _) => &[IntegerType::UnsignedLong, IntegerType::UnsignedLongLong],
+        (false, _, true) => &[IntegerType::LongLong],
+        (false, _, false) => &[IntegerType::LongLong, IntegerType::UnsignedLongLong],
+        (true, _, _) => &[IntegerType::UnsignedLongLong],
+    };
+    match candidates.iter().find(|ty| v <= ty.max_value()) {
+        Some(ty) => (v as i64, *ty),
+        None => panic!("integer literal is too large for its type: {}", s),
+    }
+}
+
+impl IntegerType {
+    fn max_value(&self) -> u64 {
+        match self {
+            IntegerType::Int => i32::MAX as u64,
+            IntegerType::UnsignedInt => u32::MAX as u64,
+            IntegerType::Long | IntegerType::LongLong => i64::MAX as u64,
+            IntegerType::UnsignedLong | IntegerType::UnsignedLongLong => u64::MAX,
+        }
+    }
+}
+
+fn read_char_literal(iter: &mut Peekable<Chars>) -> i64 {
+    let c = match iter.next() {
+        Some('\'') => panic!("empty character literal"),
+        Some('\\') => read_escape_sequence(iter),
+        Some('\n') | None => panic!("unterminated character literal"),
+        Some(c) if c.is_ascii() => c as u32,
+        Some(c) => panic!("non-ASCII character literal: {}", c),
+    };
+    match iter.next() {
+        Some('\'') => {}
+        Some('\n') | None => panic!("unterminated character literal"),
+        Some(_) => panic!("multi-character character literal"),
+    }
+    // A character constant has type int but holds a (signed) char value.
+    c as u8 as i8 as i64
+}
+
+// Returns the bytes of the literal without the terminating NUL.
+fn read_string_literal(iter: &mut Peekable<Chars>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        match iter.next() {
+            Some('"') => break,
+            Some('\\') => bytes.push(read_escape_sequence(iter) as u8),
+            Some('\n') | None => panic!("unterminated string literal"),
+            Some(c) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+// Reads the escape sequence following a backslash and returns its code.
+fn read_escape_sequence(iter: &mut Peekable<Chars>) -> u32 {
+    match iter.next() {
+        Some(c @ '0'..='7') => {
+            let mut v = c.to_digit(8).unwrap();
+            for _ in 0..2 {
+                match iter.peek().and_then(|cc| cc.to_digit(8)) {
+                    Some(d) => {
+                        v = v * 8 + d;
+                        iter.next();
+                    }
+                    None => break,
+                }
+            }
+            if v > 0xff {
+                panic!("octal escape sequence out of range: \\{:o}", v);
+            }
+            v
+        }
+        Some('x') => {
+            let mut v: u32 = 0;
+            let mut digits = 0;
+            while let Some(d) = iter.peek().and_then(|cc| cc.to_digit(16)) {
+                v = v.saturating_mul(16).saturating_add(d);
+                digits += 1;
+                iter.next();
+            }
+            if digits == 0 {
+                panic!("\\x used with no following hex digits");
+            }
+            if v > 0xff {
+                panic!("hex escape sequence out of range");
+            }
+            v
+        }
+        Some('a') => 0x07,
+        Some('b') => 0x08,
+        Some('t') => 0x09,
+        Some('n') => 0x0a,
+        Some('v') => 0x0b,
+        Some('f') => 0x0c,
+        Some('r') => 0x0d,
+        // \e (ASCII escape) is a GNU extension.
+        Some('e') => 0x1b,
+        Some(c @ ('\'' | '"' | '?' | '\\')) => c as u32,
+        Some(c) => panic!("unknown escape sequence: \\{}", c),
+        None => panic!("unexpected EOF"),
+    }
+}
@@ -0,0 +1,228 @@
+use std::{cmp::Ordering, fmt};
+
+use crate::parser::{Ast, BinaryOperator, NodeId, UnaryOperator, AST};
+
+// Why an expression is not an integer constant expression, and the node that makes it
+// so. The reason is a noun phrase such as "division by zero", to which the caller adds
+// where the constant was needed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NotConstant {
+    pub node: NodeId,
+    pub reason: String,
+}
+
+impl fmt::Display for NotConstant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+// The value of an integer constant expression. Every constant is 64 bits wide, as are
+// intmax_t and uintmax_t in `#if`; `value` holds the bits, which are read as unsigned
+// when `unsigned` is set.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Constant {
+    pub value: i64,
+    pub unsigned: bool,
+}
+
+impl Constant {
+    fn signed(value: i64) -> Self {
+        Constant {
+            value,
+            unsigned: false,
+        }
+    }
+}
+
+// Evaluates an integer constant expression. Signed operations follow C: a result that
+// does not fit, a division by zero or a shift by a negative or too large amount makes
+// the expression non-constant, as does any operand that is not itself constant.
+// Unsigned operations wrap around. Operands that are not evaluated, such as the right
+// operand of `0 && x`, are not checked.
+pub fn evaluate(ast: &Ast, id: NodeId) -> Result<Constant, NotConstant> {
+    let not_constant = |reason: &str| {
+        Err(NotConstant {
+            node: id,
+            reason: reason.to_string(),
+        })
+    };
+    match &ast[id] {
+        AST::NumberLiteral(node) => Ok(Constant {
+            value: node.value,
+            unsigned: node.type_.is_unsigned(),
+        }),
+        AST::UnaryOperation(node) => {
+            let v = match node.op {
+                UnaryOperator::Deref => return not_constant("dereference"),
+                UnaryOperator::AddrOf => return not_constant("address-of operator"),
+                UnaryOperator::PreIncrement | UnaryOperator::PostIncrement => {
+                    return not_constant("increment");
+                }
+                UnaryOperator::PreDecrement | UnaryOperator::PostDecrement => {
+                    return not_constant("decrement");
+                }
+                // The operand is not evaluated, so it need not be constant itself.
+                UnaryOperator::Sizeof => {
+                    return match ast.types.get(node.operand) {
+                        Some(type_) => Ok(Constant {
+                            value: type_.size(),
+                            unsigned: true,
+                        }),
+                        None => not_constant("sizeof operator"),
+                    };
+                }
+                _ => evaluate(ast, node.operand)?,
+            };
+            let value = match node.op {
+                UnaryOperator::Neg if v.unsigned => Some(v.value.wrapping_neg()),
+                UnaryOperator::Neg => v.value.checked_neg(),
+                UnaryOperator::Plus => Some(v.value),
+                UnaryOperator::Not => return Ok(Constant::signed((v.value == 0) as i64)),
+                UnaryOperator::BitNot => Some(!v.value),
+                UnaryOperator::Deref
+                | UnaryOperator::AddrOf
+                | UnaryOperator::PreIncrement
+                | UnaryOperator::PreDecrement
+                | UnaryOperator::PostIncrement
+                | UnaryOperator::PostDecrement
+                | UnaryOperator::Sizeof => unreachable!(),
+            };
+            match value {
+                Some(value) => Ok(Constant { value, ..v }),
+                None => not_constant("integer overflow"),
+            }
+        }
+        AST::BinaryOperation(node) if node.op == BinaryOperator::Assign => {
+            not_constant("assignment")
+        }
+        AST::CompoundAssignment(_) => not_constant("assignment"),
+        AST::BinaryOperation(node) if node.op == BinaryOperator::Comma => {
+            not_constant("comma operator")
+        }
+        AST::BinaryOperation(node) if node.op == BinaryOperator::LogicalAnd => {
+            Ok(Constant::signed(
+                (evaluate(ast, node.lhs)?.value != 0 && evaluate(ast, node.rhs)?.value != 0) as i64,
+            ))
+        }
+        AST::BinaryOperation(node) if node.op == BinaryOperator::LogicalOr => Ok(Constant::signed(
+            (evaluate(ast, node.lhs)?.value != 0 || evaluate(ast, node.rhs)?.value != 0) as i64,
+        )),
+        AST::Conditional(node) => {
+            let v = if evaluate(ast, node.condition)?.value != 0 {
+                evaluate(ast, node.then)?
+            } else {
+                evaluate(ast, node.else_)?
+            };
+            // The result has the common type of both arms, even the one not evaluated.
+            Ok(Constant {
+                value: v.value,
+                unsigned: is_unsigned(ast, node.then) || is_unsigned(ast, node.else_),
+            })
+        }
+        AST::BinaryOperation(node) => {
+            let lhs = evaluate(ast, node.lhs)?;
+            let rhs = evaluate(ast, node.rhs)?;
+            // The usual arithmetic conversions: a signed operand is converted to unsigned
+            // when the other one is unsigned.
+            let unsigned = lhs.unsigned || rhs.unsigned;
+            let (l, r) = (lhs.value, rhs.value);
+            let (ul, ur) = (l as u64, r as u64);
+            let ordering = if unsigned { ul.cmp(&ur) } else { l.cmp(&r) };
+            let compare =
+                |holds: fn(Ordering) -> bool| Some(Constant::signed(holds(ordering) as i64));
+            let arithmetic = |value: Option<i64>| value.map(|value| Constant { value, unsigned });
+            let v = match node.op {
+                BinaryOperator::Add if unsigned => arithmetic(Some(l.wrapping_add(r))),
+                BinaryOperator::Add => arithmetic(l.checked_add(r)),
+                BinaryOperator::Sub if unsigned => arithmetic(Some(l.wrapping_sub(r))),
+                BinaryOperator::Sub => arithmetic(l.checked_sub(r)),
+                BinaryOperator::Multiply if unsigned => arithmetic(Some(l.wrapping_mul(r))),
+                BinaryOperator::Multiply => arithmetic(l.checked_mul(r)),
+                BinaryOperator::Divide | BinaryOperator::Remainder if r == 0 => {
+                    return not_constant("division by zero");
+                }
+                BinaryOperator::Divide if unsigned => arithmetic(Some((ul / ur) as i64)),
+                BinaryOperator::Divide => arithmetic(l.checked_div(r)),
+                BinaryOperator::Remainder if unsigned => arithmetic(Some((ul % ur) as i64)),
+                BinaryOperator::Remainder => arithmetic(l.checked_rem(r)),
+                // A shift has the type of its left operand.
+                BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight
+                    if !(rhs.unsigned || r >= 0) || ur >= u64::from(i64::BITS) =>
+                {
+                    return not_constant("shift count out of range");
+                }
+                BinaryOperator::ShiftLeft if lhs.unsigned => Some(Constant {
+                    value: l << r,
+                    ..lhs
+                }),
+                BinaryOperator::ShiftLeft if l < 0 => {
+                    return not_constant("left shift of negative value");
+                }
+                // The bits shifted out must all be zero, and so must the sign bit.
+                BinaryOperator::ShiftLeft => {
+                    Some(l << r).filter(|v| v >> r == l).map(Constant::signed)
+                }
+                BinaryOperator::ShiftRight if lhs.unsigned => Some(Constant {
+                    value: (ul >> r) as i64,
+                    ..lhs
+                }),
+                BinaryOperator::ShiftRight => Some(Constant::signed(l >> r)),
+                BinaryOperator::BitAnd => arithmetic(Some(l & r)),
+                BinaryOperator::BitXor => arithmetic(Some(l ^ r)),
+                BinaryOperator::BitOr => arithmetic(Some(l | r)),
+                BinaryOperator::Equal => Some(Constant::signed((l == r) as i64)),
+                BinaryOperator::NotEqual => Some(Constant::signed((l != r) as i64)),
+                BinaryOperator::LessThan => compare(|o| o.is_lt()),
+                BinaryOperator::LessThanOrEqual => compare(|o| o.is_le()),
+                BinaryOperator::GreaterThan => compare(|o| o.is_gt()),
+                BinaryOperator::GreaterThanOrEqual => compare(|o| o.is_ge()),
+                BinaryOperator::LogicalAnd
+                | BinaryOperator::LogicalOr
+                | BinaryOperator::Assign
+                | BinaryOperator::Comma => unreachable!(),
+            };
+            match v {
+                Some(v) => Ok(v),
+                None => not_constant("integer overflow"),
+            }
+        }
+        AST::Identifier(name) => not_constant(&format!("use of variable {}", name)),
+        AST::StringLiteral(_) => not_constant("string literal"),
+        AST::Return(_)
+        | AST::If(_)
+        | AST::While(_)
+        | AST::For(_)
+        | AST::Block(_)
+        | AST::Declaration(_) => not_constant("statement"),
+    }
+}
+
+// Whether an expression has an unsigned type, worked out without evaluating it, for the
+// arm of `?:` that is not evaluated.
+fn is_unsigned(ast: &Ast, id: NodeId) -> bool {
+    match &ast[id] {
+        AST::NumberLiteral(node) => node.type_.is_unsigned(),
+        AST::UnaryOperation(node) => match node.op {
+            UnaryOperator::Neg | UnaryOperator::Plus | UnaryOperator::BitNot => {
+                is_unsigned(ast, node.operand)
+            }
+            UnaryOperator::Sizeof => true,
+            _ => false,
+        },
+        AST::BinaryOperation(node) => match node.op {
+            BinaryOperator::Add
+            | BinaryOperator::Sub
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Remainder
+            | BinaryOperator::BitAnd
+            | BinaryOperator::BitXor
+            | BinaryOperator::BitOr => is_unsigned(ast, node.lhs) || is_unsigned(ast, node.rhs),
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => is_unsigned(ast, node.lhs),
+            _ => false,
+        },
+        AST::Conditional(node) => is_unsigned(ast, node.then) || is_unsigned(ast, node.else_),
+        _ => false,
+    }
+}
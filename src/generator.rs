@@ -1,11 +1,36 @@
-use crate::parser::{BinaryOperator, AST};
+use std::collections::HashMap;
+
+use crate::{
+    parser::{
+        Ast, BinaryOperator, GlobalVariable, NodeId, NumberLiteralAST, Type, TypeKind,
+        UnaryOperator, Variable, AST,
+    },
+    symbol::Symbol,
+};
+
+// The contents of a string literal, stored once in .rodata however often it is used.
+struct StringLiteral {
+    label: Symbol,
+    bytes: Vec<u8>,
+}
 
 pub struct Generator {
     label_count: i64,
+    string_literal_map: HashMap<Vec<u8>, Symbol>,
+    string_literals: Vec<StringLiteral>,
+}
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Generator {
     pub fn new() -> Self {
-        Self { label_count: 0 }
+        Self {
+            label_count: 0,
+            string_literal_map: HashMap::new(),
+            string_literals: Vec::new(),
+        }
     }
     pub fn prologue(&self) {
         println!(".intel_syntax noprefix");
@@ -17,71 +42,113 @@ impl Generator {
         println!("  pop rbp");
         println!("  ret");
     }
+    pub fn data(&self, global_variables: &[GlobalVariable]) {
+        for v in global_variables {
+            if v.qualifiers.const_ {
+                println!(".section .rodata");
+            } else if v.init.is_some() {
+                println!(".data");
+            } else {
+                println!(".bss");
+            }
+            println!("{}:", v.label);
+            match v.init {
+                Some(init) => println!("  .quad {}", init),
+                None => println!("  .zero 8"),
+            }
+        }
+    }
+    // Emits the string literals used by the code generated so far.
+    pub fn string_literals(&self) {
+        for s in &self.string_literals {
+            println!(".section .rodata");
+            println!("{}:", s.label);
+            let bytes = s
+                .bytes
+                .iter()
+                .chain(&[0])
+                .map(|b| b.to_string())
+                .collect::<Vec<String>>();
+            println!("  .byte {}", bytes.join(", "));
+        }
+    }
     pub fn allocate_local_variables(&self, local_variable_count: usize) {
         println!("  push rbp");
         println!("  mov rbp, rsp");
         println!("  sub rsp, {}", 8 * local_variable_count);
     }
-    pub fn gen(&mut self, ast: AST) {
-        if let AST::Block(nodes) = ast {
-            for node in nodes {
-                self.gen(node);
+    pub fn gen(&mut self, ast: &Ast, id: NodeId) {
+        let node = &ast[id];
+        if let AST::Block(nodes) = node {
+            for &node in nodes {
+                self.gen(ast, node);
                 // println!("  pop rax");
             }
             return;
         }
-        if let AST::Return(node) = ast {
-            self.gen(*node);
+        if let AST::Declaration(node) = node {
+            // Only locals are initialized at run time; statics are emitted as data.
+            if let (Some(Variable::Local(_)), Some(init)) = (ast.symbols.get(id), node.init) {
+                self.gen_lval(ast, id);
+                self.gen(ast, init);
+                println!("  pop rdi");
+                println!("  pop rax");
+                println!("  mov [rax], rdi");
+            }
+            return;
+        }
+        if let AST::Return(node) = *node {
+            self.gen(ast, node);
             println!("  pop rax");
             println!("  mov rsp, rbp");
             println!("  pop rbp");
             println!("  ret");
             return;
         }
-        if let AST::If(node) = ast {
+        if let AST::If(node) = node {
             if let Some(else_) = node.else_ {
-                self.gen(*node.condition);
+                self.gen(ast, node.condition);
                 println!("  pop rax");
                 println!("  cmp rax, 0");
                 let else_label = format!(".Lelse{}", self.label_count);
                 self.label_count += 1;
                 println!("  je {}", else_label);
-                self.gen(*node.then);
+                self.gen(ast, node.then);
                 let end_label = format!(".Lend{}", self.label_count);
                 println!("  jmp {}", end_label);
                 println!("{}:", else_label);
-                self.gen(*else_);
+                self.gen(ast, else_);
                 println!("{}:", end_label);
                 return;
             }
-            self.gen(*node.condition);
+            self.gen(ast, node.condition);
             println!("  pop rax");
             println!("  cmp rax, 0");
             let end_label = format!(".Lend{}", self.label_count);
             self.label_count += 1;
             println!("  je {}", end_label);
-            self.gen(*node.then);
+            self.gen(ast, node.then);
             println!("{}:", end_label);
             return;
         }
-        if let AST::While(node) = ast {
+        if let AST::While(node) = node {
             let begin_label = format!(".Lbegin{}", self.label_count);
             self.label_count += 1;
             println!("{}:", begin_label);
-            self.gen(*node.condition);
+            self.gen(ast, node.condition);
             println!("  pop rax");
             println!("  cmp rax, 0");
             let end_label = format!(".Lend{}", self.label_count);
             self.label_count += 1;
             println!("  je {}", end_label);
-            self.gen(*node.body);
+            self.gen(ast, node.body);
             println!("  jmp {}", begin_label);
             println!("{}:", end_label);
             return;
         }
-        if let AST::For(node) = ast {
+        if let AST::For(node) = node {
             if let Some(init) = node.init {
-                self.gen(*init);
+                self.gen(ast, init);
             }
             let begin_label = format!(".Lbegin{}", self.label_count);
             self.label_count += 1;
@@ -89,95 +156,364 @@ impl Generator {
             let end_label = format!(".Lend{}", self.label_count);
             self.label_count += 1;
             if let Some(condition) = node.condition {
-                self.gen(*condition);
+                self.gen(ast, condition);
                 println!("  pop rax");
                 println!("  cmp rax, 0");
                 println!("  je {}", end_label);
             }
-            self.gen(*node.body);
+            self.gen(ast, node.body);
             if let Some(update) = node.update {
-                self.gen(*update);
+                self.gen(ast, update);
             }
             println!("  jmp {}", begin_label);
             println!("{}:", end_label);
             return;
         }
-        if let AST::NumberLiteral(v) = ast {
-            println!("  push {}", v);
+        if let AST::NumberLiteral(NumberLiteralAST { value: v, .. }) = *node {
+            // push only takes a sign-extended 32-bit immediate.
+            if i32::try_from(v).is_ok() {
+                println!("  push {}", v);
+            } else {
+                println!("  mov rax, {}", v);
+                println!("  push rax");
+            }
+            return;
+        }
+        if let AST::StringLiteral(v) = node {
+            let label = self.string_literal_label(&v.bytes);
+            println!("  lea rax, [rip+{}]", label);
+            println!("  push rax");
             return;
         }
-        if let AST::LocalVariable(_) = ast {
-            self.gen_lval(ast);
+        if let AST::Identifier(_) = node {
+            self.gen_lval(ast, id);
             println!("  pop rax");
-            println!("  mov rax, [rax]");
+            load(ast.types.get(id));
             println!("  push rax");
             return;
         }
 
-        if let AST::BinaryOperation(node) = ast {
+        if let AST::UnaryOperation(node) = node {
+            match node.op {
+                UnaryOperator::AddrOf => {
+                    self.gen_lval(ast, node.operand);
+                    return;
+                }
+                UnaryOperator::Sizeof => {
+                    let size = ast.types.get(node.operand).map_or(8, Type::size);
+                    println!("  push {}", size);
+                    return;
+                }
+                UnaryOperator::PreIncrement
+                | UnaryOperator::PreDecrement
+                | UnaryOperator::PostIncrement
+                | UnaryOperator::PostDecrement => {
+                    self.gen_increment(ast, node.op, node.operand);
+                    return;
+                }
+                _ => {}
+            }
+            self.gen(ast, node.operand);
+            // Only `*` depends on the type, which tells how wide a load it makes; the
+            // other operators work on the full 64-bit value.
+            match node.op {
+                UnaryOperator::Neg => {
+                    println!("  pop rax");
+                    println!("  neg rax");
+                    println!("  push rax");
+                }
+                UnaryOperator::Plus => {}
+                UnaryOperator::Not => {
+                    println!("  pop rax");
+                    println!("  cmp rax, 0");
+                    println!("  sete al");
+                    println!("  movzb rax, al");
+                    println!("  push rax");
+                }
+                UnaryOperator::BitNot => {
+                    println!("  pop rax");
+                    println!("  not rax");
+                    println!("  push rax");
+                }
+                UnaryOperator::Deref => {
+                    println!("  pop rax");
+                    load(ast.types.get(id));
+                    println!("  push rax");
+                }
+                UnaryOperator::AddrOf
+                | UnaryOperator::Sizeof
+                | UnaryOperator::PreIncrement
+                | UnaryOperator::PreDecrement
+                | UnaryOperator::PostIncrement
+                | UnaryOperator::PostDecrement => unreachable!(),
+            }
+            return;
+        }
+        if let AST::Conditional(node) = node {
+            self.gen(ast, node.condition);
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            let else_label = format!(".Lelse{}", self.label_count);
+            self.label_count += 1;
+            println!("  je {}", else_label);
+            self.gen(ast, node.then);
+            let end_label = format!(".Lend{}", self.label_count);
+            self.label_count += 1;
+            println!("  jmp {}", end_label);
+            println!("{}:", else_label);
+            self.gen(ast, node.else_);
+            println!("{}:", end_label);
+            return;
+        }
+
+        if let AST::BinaryOperation(node) = node {
+            if let BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr = node.op {
+                // The right operand is only evaluated when the left one does not decide
+                // the result.
+                let (short_circuit, jump) = match node.op {
+                    BinaryOperator::LogicalAnd => (0, "je"),
+                    _ => (1, "jne"),
+                };
+                let short_label = format!(".Lshort{}", self.label_count);
+                let end_label = format!(".Lend{}", self.label_count);
+                self.label_count += 1;
+                self.gen(ast, node.lhs);
+                println!("  pop rax");
+                println!("  cmp rax, 0");
+                println!("  {} {}", jump, short_label);
+                self.gen(ast, node.rhs);
+                println!("  pop rax");
+                println!("  cmp rax, 0");
+                println!("  {} {}", jump, short_label);
+                println!("  push {}", 1 - short_circuit);
+                println!("  jmp {}", end_label);
+                println!("{}:", short_label);
+                println!("  push {}", short_circuit);
+                println!("{}:", end_label);
+                return;
+            }
+            if BinaryOperator::Comma == node.op {
+                self.gen(ast, node.lhs);
+                println!("  pop rax");
+                self.gen(ast, node.rhs);
+                return;
+            }
             if BinaryOperator::Assign == node.op {
-                self.gen_lval(*node.lhs);
-                self.gen(*node.rhs);
+                self.gen_lval(ast, node.lhs);
+                self.gen(ast, node.rhs);
 
                 println!("  pop rdi");
                 println!("  pop rax");
-                println!("  mov [rax], rdi");
+                store(ast.types.get(node.lhs));
                 println!("  push rdi");
                 return;
             }
 
-            self.gen(*node.lhs);
-            self.gen(*node.rhs);
+            self.gen(ast, node.lhs);
+            self.gen(ast, node.rhs);
 
             println!("  pop rdi");
             println!("  pop rax");
+            self.gen_operator(node.op, ast.types.get(node.lhs), ast.types.get(node.rhs));
+            println!("  push rax");
+            return;
+        }
 
-            match node.op {
-                BinaryOperator::Add => {
-                    println!("  add rax, rdi");
-                }
-                BinaryOperator::Sub => {
-                    println!("  sub rax, rdi");
-                }
-                BinaryOperator::Multiply => {
-                    println!("  imul rax, rdi");
-                }
-                BinaryOperator::Divide => {
+        if let AST::CompoundAssignment(node) = node {
+            // The address is computed once and kept on the stack while the old value is
+            // loaded and combined with the right operand.
+            self.gen_lval(ast, node.lhs);
+            println!("  mov rax, [rsp]");
+            load(ast.types.get(node.lhs));
+            println!("  push rax");
+            self.gen(ast, node.rhs);
+            println!("  pop rdi");
+            println!("  pop rax");
+            self.gen_operator(node.op, ast.types.get(node.lhs), ast.types.get(node.rhs));
+            println!("  mov rdi, rax");
+            println!("  pop rax");
+            store(ast.types.get(node.lhs));
+            println!("  push rdi");
+        }
+    }
+    // Applies a binary operator to rax and rdi, whose types are `lhs` and `rhs`, leaving
+    // the result in rax. Adding an integer to a pointer moves it by whole elements, and
+    // the difference of two pointers counts elements, so these are scaled by the size of
+    // the pointee. As in the constant evaluator, an unsigned operand makes the operation
+    // unsigned, except that a shift only looks at its left operand.
+    fn gen_operator(&self, op: BinaryOperator, lhs: Option<&Type>, rhs: Option<&Type>) {
+        let lhs_unsigned = lhs.is_some_and(Type::is_unsigned);
+        let unsigned = lhs_unsigned || rhs.is_some_and(Type::is_unsigned);
+        let lhs = lhs.and_then(Type::pointee).map(Type::size);
+        let rhs = rhs.and_then(Type::pointee).map(Type::size);
+        match (op, lhs, rhs) {
+            (BinaryOperator::Add | BinaryOperator::Sub, Some(size), None) if size != 1 => {
+                println!("  imul rdi, {}", size);
+            }
+            (BinaryOperator::Add, None, Some(size)) if size != 1 => {
+                println!("  imul rax, {}", size);
+            }
+            _ => {}
+        }
+        match op {
+            BinaryOperator::Add => {
+                println!("  add rax, rdi");
+            }
+            BinaryOperator::Sub => {
+                println!("  sub rax, rdi");
+            }
+            BinaryOperator::Multiply => {
+                println!("  imul rax, rdi");
+            }
+            BinaryOperator::Divide | BinaryOperator::Remainder => {
+                if unsigned {
+                    println!("  mov rdx, 0");
+                    println!("  div rdi");
+                } else {
                     println!("  cqo");
                     println!("  idiv rdi");
                 }
-                BinaryOperator::Equal => {
-                    println!("  cmp rax, rdi");
-                    println!("  sete al");
-                    println!("  movzb rax, al");
-                }
-                BinaryOperator::NotEqual => {
-                    println!("  cmp rax, rdi");
-                    println!("  setne al");
-                    println!("  movzb rax, al");
+                if op == BinaryOperator::Remainder {
+                    println!("  mov rax, rdx");
                 }
-                BinaryOperator::LessThan => {
-                    println!("  cmp rax, rdi");
-                    println!("  setl al");
-                    println!("  movzb rax, al");
-                }
-                BinaryOperator::LessThanOrEqual => {
-                    println!("  cmp rax, rdi");
-                    println!("  setle al");
-                    println!("  movzb rax, al");
-                }
-                _ => unreachable!(),
             }
-            println!("  push rax");
+            BinaryOperator::ShiftLeft => {
+                println!("  mov rcx, rdi");
+                println!("  sal rax, cl");
+            }
+            BinaryOperator::ShiftRight => {
+                println!("  mov rcx, rdi");
+                println!("  {} rax, cl", if lhs_unsigned { "shr" } else { "sar" });
+            }
+            BinaryOperator::BitAnd => {
+                println!("  and rax, rdi");
+            }
+            BinaryOperator::BitXor => {
+                println!("  xor rax, rdi");
+            }
+            BinaryOperator::BitOr => {
+                println!("  or rax, rdi");
+            }
+            BinaryOperator::Equal => {
+                println!("  cmp rax, rdi");
+                println!("  sete al");
+                println!("  movzb rax, al");
+            }
+            BinaryOperator::NotEqual => {
+                println!("  cmp rax, rdi");
+                println!("  setne al");
+                println!("  movzb rax, al");
+            }
+            BinaryOperator::LessThan => {
+                println!("  cmp rax, rdi");
+                println!("  {} al", if unsigned { "setb" } else { "setl" });
+                println!("  movzb rax, al");
+            }
+            BinaryOperator::LessThanOrEqual => {
+                println!("  cmp rax, rdi");
+                println!("  {} al", if unsigned { "setbe" } else { "setle" });
+                println!("  movzb rax, al");
+            }
+            BinaryOperator::GreaterThan => {
+                println!("  cmp rax, rdi");
+                println!("  {} al", if unsigned { "seta" } else { "setg" });
+                println!("  movzb rax, al");
+            }
+            BinaryOperator::GreaterThanOrEqual => {
+                println!("  cmp rax, rdi");
+                println!("  {} al", if unsigned { "setae" } else { "setge" });
+                println!("  movzb rax, al");
+            }
+            _ => unreachable!(),
+        }
+        if let (BinaryOperator::Sub, Some(size), Some(_)) = (op, lhs, rhs) {
+            if size != 1 {
+                println!("  mov rdi, {}", size);
+                println!("  cqo");
+                println!("  idiv rdi");
+            }
+        }
+    }
+    // Adds or subtracts one to `operand`, or one element if it is a pointer, and pushes
+    // the new value for the prefix operators and the old one for the postfix ones. Like
+    // a compound assignment, it computes the address only once.
+    fn gen_increment(&mut self, ast: &Ast, op: UnaryOperator, operand: NodeId) {
+        let type_ = ast.types.get(operand);
+        let step = type_.and_then(Type::pointee).map_or(1, Type::size);
+        self.gen_lval(ast, operand);
+        println!("  mov rax, [rsp]");
+        load(type_);
+        println!("  mov rsi, rax");
+        match op {
+            UnaryOperator::PreIncrement | UnaryOperator::PostIncrement => {
+                println!("  add rax, {}", step);
+            }
+            _ => println!("  sub rax, {}", step),
+        }
+        println!("  mov rdi, rax");
+        println!("  pop rax");
+        store(type_);
+        match op {
+            UnaryOperator::PreIncrement | UnaryOperator::PreDecrement => println!("  push rdi"),
+            _ => println!("  push rsi"),
+        }
+    }
+    // The label of the string literal holding `bytes`, which is shared by every literal
+    // with the same contents.
+    fn string_literal_label(&mut self, bytes: &[u8]) -> Symbol {
+        if let Some(&label) = self.string_literal_map.get(bytes) {
+            return label;
         }
+        let label = Symbol::intern(&format!(".LC{}", self.string_literals.len()));
+        self.string_literal_map.insert(bytes.to_vec(), label);
+        self.string_literals.push(StringLiteral {
+            label,
+            bytes: bytes.to_vec(),
+        });
+        label
     }
-    fn gen_lval(&self, ast: AST) {
-        if let AST::LocalVariable(v) = ast {
+    fn gen_lval(&mut self, ast: &Ast, id: NodeId) {
+        if let Some(Variable::Local(v)) = ast.symbols.get(id) {
             println!("  mov rax, rbp");
             println!("  sub rax, {}", v.offset);
             println!("  push rax");
             return;
         }
-        panic!("invalid lval");
+        if let Some(Variable::Global(v)) = ast.symbols.get(id) {
+            println!("  lea rax, [rip+{}]", v.label);
+            println!("  push rax");
+            return;
+        }
+        // The address `*p` designates is the value of `p`.
+        if let AST::UnaryOperation(node) = &ast[id] {
+            if node.op == UnaryOperator::Deref {
+                self.gen(ast, node.operand);
+                return;
+            }
+        }
+        // A string literal already evaluates to its address.
+        if let AST::StringLiteral(_) = &ast[id] {
+            self.gen(ast, id);
+            return;
+        }
+        // Sema rejects assignments to and addresses of anything else.
+        unreachable!("invalid lval");
+    }
+}
+
+// Replaces the address in rax with the value stored there. A `char` is sign-extended;
+// everything else is 8 bytes wide.
+fn load(type_: Option<&Type>) {
+    match type_.map(|t| &t.kind) {
+        Some(TypeKind::Char) => println!("  movsx rax, byte ptr [rax]"),
+        _ => println!("  mov rax, [rax]"),
+    }
+}
+
+// Stores rdi at the address in rax, writing as many bytes as `type_` takes.
+fn store(type_: Option<&Type>) {
+    match type_.map(|t| &t.kind) {
+        Some(TypeKind::Char) => println!("  mov [rax], dil"),
+        _ => println!("  mov [rax], rdi"),
     }
 }
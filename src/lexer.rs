@@ -1,192 +1,763 @@
+use std::{borrow::Cow, fmt, rc::Rc};
+
+use crate::symbol::Symbol;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Token {
-    Identifier(String),
-    Number(i64),
+    Identifier(Symbol),
+    // A preprocessing number, character constant or string literal, as spelled in the
+    // source. Its value is only worked out by the parser, since the preprocessor may
+    // stringize or paste it, or skip it altogether.
+    Number(Symbol),
+    Character(Symbol),
+    String(Symbol),
     Keyword(Keyword),
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Period,
+    Arrow,
+    Increment,
+    Decrement,
+    Ampersand,
+    Asterisk,
     Plus,
     Minus,
-    Asterisk,
+    Tilde,
+    Exclamation,
     Slash,
-    LeftParen,
-    RightParen,
-    Assign,
-    Equal,
-    NotEqual,
-    GreaterThan,
-    GreaterThanOrEqual,
+    Percent,
+    LeftShift,
+    RightShift,
     LessThan,
+    GreaterThan,
     LessThanOrEqual,
+    GreaterThanOrEqual,
+    Equal,
+    NotEqual,
+    Caret,
+    Pipe,
+    LogicalAnd,
+    LogicalOr,
+    Question,
+    Colon,
     SemiColon,
-    LeftBrace,
-    RightBrace,
+    Ellipsis,
+    Assign,
+    MultiplyAssign,
+    DivideAssign,
+    ModuloAssign,
+    AddAssign,
+    SubtractAssign,
+    LeftShiftAssign,
+    RightShiftAssign,
+    AndAssign,
+    XorAssign,
+    OrAssign,
+    Comma,
+    Hash,
+    HashHash,
+    // Any other character, such as `@` or an unmatched quote. The preprocessor passes it
+    // through, but it is not valid in a program.
+    Other(char),
+    // Only seen by the preprocessor, which needs line boundaries for directives and
+    // whitespace for function-like macro definitions and stringification.
+    Newline,
+    Space,
     EOF,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+// The type of an integer constant, deduced from its magnitude and suffix.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IntegerType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Keyword {
-    Return,
-    If,
+    Auto,
+    Break,
+    Case,
+    Char,
+    Const,
+    Continue,
+    Default,
+    Do,
+    Double,
     Else,
-    While,
+    Enum,
+    Extern,
+    Float,
     For,
+    Goto,
+    If,
+    Inline,
+    Int,
+    Long,
+    Register,
+    Restrict,
+    Return,
+    Short,
+    Signed,
+    Sizeof,
+    Static,
+    Struct,
+    Switch,
+    Typedef,
+    Union,
+    Unsigned,
+    Void,
+    Volatile,
+    While,
+    Alignas,
+    Alignof,
+    Atomic,
+    Bool,
+    Complex,
+    Generic,
+    Imaginary,
+    Noreturn,
+    StaticAssert,
+    ThreadLocal,
 }
 
-pub fn tokenize(s: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut iter = s.chars().peekable();
-    loop {
-        match iter.peek() {
-            Some(&c) if c.is_whitespace() => {
-                iter.next();
+// The keywords of C11 6.4.1.
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("auto", Keyword::Auto),
+    ("break", Keyword::Break),
+    ("case", Keyword::Case),
+    ("char", Keyword::Char),
+    ("const", Keyword::Const),
+    ("continue", Keyword::Continue),
+    ("default", Keyword::Default),
+    ("do", Keyword::Do),
+    ("double", Keyword::Double),
+    ("else", Keyword::Else),
+    ("enum", Keyword::Enum),
+    ("extern", Keyword::Extern),
+    ("float", Keyword::Float),
+    ("for", Keyword::For),
+    ("goto", Keyword::Goto),
+    ("if", Keyword::If),
+    ("inline", Keyword::Inline),
+    ("int", Keyword::Int),
+    ("long", Keyword::Long),
+    ("register", Keyword::Register),
+    ("restrict", Keyword::Restrict),
+    ("return", Keyword::Return),
+    ("short", Keyword::Short),
+    ("signed", Keyword::Signed),
+    ("sizeof", Keyword::Sizeof),
+    ("static", Keyword::Static),
+    ("struct", Keyword::Struct),
+    ("switch", Keyword::Switch),
+    ("typedef", Keyword::Typedef),
+    ("union", Keyword::Union),
+    ("unsigned", Keyword::Unsigned),
+    ("void", Keyword::Void),
+    ("volatile", Keyword::Volatile),
+    ("while", Keyword::While),
+    ("_Alignas", Keyword::Alignas),
+    ("_Alignof", Keyword::Alignof),
+    ("_Atomic", Keyword::Atomic),
+    ("_Bool", Keyword::Bool),
+    ("_Complex", Keyword::Complex),
+    ("_Generic", Keyword::Generic),
+    ("_Imaginary", Keyword::Imaginary),
+    ("_Noreturn", Keyword::Noreturn),
+    ("_Static_assert", Keyword::StaticAssert),
+    ("_Thread_local", Keyword::ThreadLocal),
+];
+
+// The punctuators of C11 6.4.6, in their canonical spelling.
+const PUNCTUATORS: &[(&str, Token)] = &[
+    ("[", Token::LeftBracket),
+    ("]", Token::RightBracket),
+    ("(", Token::LeftParen),
+    (")", Token::RightParen),
+    ("{", Token::LeftBrace),
+    ("}", Token::RightBrace),
+    (".", Token::Period),
+    ("->", Token::Arrow),
+    ("++", Token::Increment),
+    ("--", Token::Decrement),
+    ("&", Token::Ampersand),
+    ("*", Token::Asterisk),
+    ("+", Token::Plus),
+    ("-", Token::Minus),
+    ("~", Token::Tilde),
+    ("!", Token::Exclamation),
+    ("/", Token::Slash),
+    ("%", Token::Percent),
+    ("<<", Token::LeftShift),
+    (">>", Token::RightShift),
+    ("<", Token::LessThan),
+    (">", Token::GreaterThan),
+    ("<=", Token::LessThanOrEqual),
+    (">=", Token::GreaterThanOrEqual),
+    ("==", Token::Equal),
+    ("!=", Token::NotEqual),
+    ("^", Token::Caret),
+    ("|", Token::Pipe),
+    ("&&", Token::LogicalAnd),
+    ("||", Token::LogicalOr),
+    ("?", Token::Question),
+    (":", Token::Colon),
+    (";", Token::SemiColon),
+    ("...", Token::Ellipsis),
+    ("=", Token::Assign),
+    ("*=", Token::MultiplyAssign),
+    ("/=", Token::DivideAssign),
+    ("%=", Token::ModuloAssign),
+    ("+=", Token::AddAssign),
+    ("-=", Token::SubtractAssign),
+    ("<<=", Token::LeftShiftAssign),
+    (">>=", Token::RightShiftAssign),
+    ("&=", Token::AndAssign),
+    ("^=", Token::XorAssign),
+    ("|=", Token::OrAssign),
+    (",", Token::Comma),
+    ("#", Token::Hash),
+    ("##", Token::HashHash),
+];
+
+// Alternative spellings of punctuators. They lex like the originals but are never printed.
+const DIGRAPHS: &[(&str, Token)] = &[
+    ("<:", Token::LeftBracket),
+    (":>", Token::RightBracket),
+    ("<%", Token::LeftBrace),
+    ("%>", Token::RightBrace),
+    ("%:", Token::Hash),
+    ("%:%:", Token::HashHash),
+];
+
+impl fmt::Display for Token {
+    // Spells the token as source text.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Identifier(v) | Token::Number(v) | Token::Character(v) | Token::String(v) => {
+                write!(f, "{}", v)
             }
-            Some(&c) if '_' == c || c.is_alphabetic() => {
-                let mut ret = String::new();
-                loop {
-                    match iter.peek() {
-                        Some(&cc) if '_' == cc || cc.is_ascii_digit() || cc.is_alphabetic() => {
-                            ret.push(cc);
-                            iter.next();
-                        }
-                        _ => {
-                            break;
-                        }
-                    }
+            Token::Keyword(v) => write!(f, "{}", v),
+            Token::Other(c) => write!(f, "{}", c),
+            Token::Newline => writeln!(f),
+            Token::Space => write!(f, " "),
+            Token::EOF => Ok(()),
+            punctuator => match PUNCTUATORS.iter().find(|(_, t)| t == punctuator) {
+                Some((spelling, _)) => write!(f, "{}", spelling),
+                None => unreachable!("{:?} has no spelling", punctuator),
+            },
+        }
+    }
+}
+
+impl Token {
+    // A number token for a value computed by the preprocessor, such as `__LINE__`.
+    pub fn number(v: i64) -> Token {
+        Token::Number(Symbol::intern(&v.to_string()))
+    }
+    // A string literal holding `bytes`, such as the value of `__FILE__`.
+    pub fn string(bytes: &[u8]) -> Token {
+        let mut s = String::from("\"");
+        for &b in bytes {
+            match b {
+                b'"' | b'\\' => {
+                    s.push('\\');
+                    s.push(b as char);
                 }
-                match ret.as_str() {
-                    "return" => {
-                        tokens.push(Token::Keyword(Keyword::Return));
-                    }
-                    "if" => {
-                        tokens.push(Token::Keyword(Keyword::If));
-                    }
-                    "else" => {
-                        tokens.push(Token::Keyword(Keyword::Else));
-                    }
-                    "while" => {
-                        tokens.push(Token::Keyword(Keyword::While));
-                    }
-                    "for" => {
-                        tokens.push(Token::Keyword(Keyword::For));
-                    }
-                    _ => {
-                        tokens.push(Token::Identifier(ret));
+                b'\n' => s.push_str("\\n"),
+                b'\t' => s.push_str("\\t"),
+                0x20..=0x7e => s.push(b as char),
+                _ => s.push_str(&format!("\\{:03o}", b)),
+            }
+        }
+        s.push('"');
+        Token::String(Symbol::intern(&s))
+    }
+}
+
+impl Keyword {
+    pub fn as_str(self) -> &'static str {
+        match KEYWORDS.iter().find(|(_, k)| *k == self) {
+            Some((spelling, _)) => spelling,
+            None => unreachable!("{:?} has no spelling", self),
+        }
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Lexes source text lazily, one token at a time, ending with `Token::EOF`. It scans the
+// bytes of the input directly; only spliced lines force a copy of the source.
+pub struct Lexer<'a> {
+    source: Cow<'a, str>,
+    pos: usize,
+    // Line breaks inside block comments. They cannot end the current line, which matters
+    // to directives, so they are emitted at the next real line break to keep line counts.
+    pending_newlines: usize,
+    // Newline tokens still to be returned for the line break just read.
+    queued_newlines: usize,
+    finished: bool,
+    // The name of the file being read, for diagnostics.
+    file: Option<Rc<str>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(s: &'a str) -> Self {
+        let source = if s.contains("\\\n") {
+            Cow::Owned(splice_lines(s))
+        } else {
+            Cow::Borrowed(s)
+        };
+        Lexer {
+            source,
+            pos: 0,
+            pending_newlines: 0,
+            queued_newlines: 0,
+            finished: false,
+            file: None,
+        }
+    }
+    pub fn with_file(s: &'a str, file: Rc<str>) -> Self {
+        Lexer {
+            file: Some(file),
+            ..Self::new(s)
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.peek_nth(0)
+    }
+    fn peek_nth(&self, n: usize) -> Option<u8> {
+        self.source.as_bytes().get(self.pos + n).copied()
+    }
+    // The character at the current position, which may take several bytes.
+    fn current_char(&self) -> char {
+        self.source[self.pos..].chars().next().unwrap_or('\0')
+    }
+    // Skips whitespace other than line breaks, and comments. Returns whether anything was
+    // skipped, since a run of them is a single separator.
+    fn skip_space(&mut self) -> bool {
+        let start = self.pos;
+        loop {
+            match (self.peek(), self.peek_nth(1)) {
+                (Some(b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c'), _) => self.pos += 1,
+                (Some(b'/'), Some(b'/')) => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
                     }
                 }
-            }
-            Some(&c) if c.is_ascii_digit() => {
-                let mut ret = String::new();
-                loop {
-                    match iter.peek() {
-                        Some(&cc) if cc.is_ascii_digit() => {
-                            ret.push(cc);
-                            iter.next();
-                        }
-                        _ => {
-                            break;
+                (Some(b'/'), Some(b'*')) => {
+                    let comment = self.pos;
+                    match self.source[self.pos + 2..].find("*/") {
+                        Some(len) => {
+                            let body = &self.source[self.pos + 2..self.pos + 2 + len];
+                            self.pending_newlines += body.matches('\n').count();
+                            self.pos += len + 4;
                         }
+                        None => error_at(
+                            &self.source,
+                            self.file.as_deref(),
+                            comment,
+                            "unterminated block comment",
+                        ),
                     }
                 }
-                if let Ok(v) = ret.parse::<i64>() {
-                    tokens.push(Token::Number(v));
-                } else {
-                    panic!("failed convert: {}", ret);
-                }
-            }
-            Some(&'+') => {
-                tokens.push(Token::Plus);
-                iter.next();
-            }
-            Some(&'-') => {
-                tokens.push(Token::Minus);
-                iter.next();
+                _ => return self.pos != start,
             }
-            Some(&'*') => {
-                tokens.push(Token::Asterisk);
-                iter.next();
-            }
-            Some(&'/') => {
-                tokens.push(Token::Slash);
-                iter.next();
+        }
+    }
+    fn identifier(&mut self) -> Token {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c == b'_' || c.is_ascii_alphanumeric() || !c.is_ascii())
+        {
+            self.pos += 1;
+        }
+        let name = &self.source[start..self.pos];
+        match KEYWORDS.iter().find(|(k, _)| *k == name) {
+            Some((_, keyword)) => Token::Keyword(*keyword),
+            None => Token::Identifier(Symbol::intern(name)),
+        }
+    }
+    // Reads a preprocessing number (C11 6.4.8): a digit, or a period and a digit,
+    // followed by letters, digits, underscores, periods and the signs of exponents.
+    fn number(&mut self) -> Token {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(c) if c == b'_' || c == b'.' || c.is_ascii_alphanumeric() => self.pos += 1,
+                Some(b'+' | b'-')
+                    if matches!(
+                        self.source.as_bytes()[self.pos - 1],
+                        b'e' | b'E' | b'p' | b'P'
+                    ) =>
+                {
+                    self.pos += 1;
+                }
+                // A digit separator is only part of the number when a digit follows it.
+                Some(b'\'') if self.peek_nth(1).is_some_and(|c| c.is_ascii_alphanumeric()) => {
+                    self.pos += 1;
+                }
+                _ => break,
             }
-            Some(&'(') => {
-                tokens.push(Token::LeftParen);
-                iter.next();
+        }
+        Token::Number(Symbol::intern(&self.source[start..self.pos]))
+    }
+    // Finds the longest punctuator at the current position.
+    fn punctuator(&mut self) -> Option<Token> {
+        let rest = &self.source.as_bytes()[self.pos..];
+        let first = *rest.first()?;
+        // Comparing the first byte up front keeps the scan cheap for the common misses.
+        let (spelling, token) = PUNCTUATORS
+            .iter()
+            .chain(DIGRAPHS)
+            .filter(|(spelling, _)| {
+                spelling.as_bytes()[0] == first && rest.starts_with(spelling.as_bytes())
+            })
+            .max_by_key(|(spelling, _)| spelling.len())?;
+        self.pos += spelling.len();
+        Some(token.clone())
+    }
+    // Reads a character constant or string literal up to the closing `quote`. Its
+    // contents are only checked when it is converted. A quote that is not closed on the
+    // same line is a token of its own, as text in a skipped group may have one.
+    fn quoted(&mut self, quote: u8) -> Token {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(c) if c == quote => break,
+                Some(b'\\') if self.peek_nth(1).is_some_and(|c| c != b'\n') => self.pos += 2,
+                Some(b'\n') | None => {
+                    self.pos = start + 1;
+                    return Token::Other(quote as char);
+                }
+                Some(_) => self.pos += 1,
             }
-            Some(&')') => {
-                tokens.push(Token::RightParen);
-                iter.next();
+        }
+        self.pos += 1;
+        let spelling = Symbol::intern(&self.source[start..self.pos]);
+        if quote == b'"' {
+            Token::String(spelling)
+        } else {
+            Token::Character(spelling)
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.queued_newlines > 0 {
+            self.queued_newlines -= 1;
+            return Some(Token::Newline);
+        }
+        if self.finished {
+            return None;
+        }
+        if self.skip_space() {
+            return Some(Token::Space);
+        }
+        let token = match self.peek() {
+            Some(b'\n') => {
+                self.pos += 1;
+                self.queued_newlines = self.pending_newlines;
+                self.pending_newlines = 0;
+                Token::Newline
             }
-            Some(&'=') => {
-                iter.next();
-                match iter.peek() {
-                    Some(&'=') => {
-                        tokens.push(Token::Equal);
-                        iter.next();
-                    }
-                    _ => {
-                        tokens.push(Token::Assign);
-                    }
+            Some(c) if c == b'_' || c.is_ascii_alphabetic() || !c.is_ascii() => self.identifier(),
+            Some(c) if c.is_ascii_digit() => self.number(),
+            Some(b'.') if self.peek_nth(1).is_some_and(|c| c.is_ascii_digit()) => self.number(),
+            Some(c @ (b'\'' | b'"')) => self.quoted(c),
+            Some(_) => match self.punctuator() {
+                Some(token) => token,
+                None => {
+                    let c = self.current_char();
+                    self.pos += c.len_utf8();
+                    Token::Other(c)
                 }
+            },
+            None => {
+                self.finished = true;
+                Token::EOF
             }
-            Some(&'!') => {
-                iter.next();
-                match iter.peek() {
-                    Some(&'=') => {
-                        tokens.push(Token::NotEqual);
-                        iter.next();
-                    }
-                    Some(&cc) => {
-                        panic!("unexpected character: {}", cc);
-                    }
-                    None => {
-                        panic!("unexpected EOF");
-                    }
-                }
+        };
+        Some(token)
+    }
+}
+
+// Deletes each backslash-newline pair, joining physical lines into logical ones
+// (translation phase 2). The deleted line breaks are put back after the logical line
+// so that later lines keep their line numbers.
+fn splice_lines(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut spliced = 0;
+    let mut iter = s.chars().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' if iter.next_if_eq(&'\n').is_some() => spliced += 1,
+            '\n' => {
+                ret.push('\n');
+                ret.extend(std::iter::repeat_n('\n', spliced));
+                spliced = 0;
             }
-            Some(&'>') => {
-                iter.next();
-                match iter.peek() {
-                    Some(&'=') => {
-                        tokens.push(Token::GreaterThanOrEqual);
-                        iter.next();
-                    }
-                    _ => {
-                        tokens.push(Token::GreaterThan);
-                    }
-                }
+            _ => ret.push(c),
+        }
+    }
+    ret.extend(std::iter::repeat_n('\n', spliced));
+    ret
+}
+
+// Replaces the nine trigraph sequences such as `??=` with the characters they stand for
+// (translation phase 1). Trigraphs are rare and surprising, so callers opt into this.
+pub fn replace_trigraphs(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("??") {
+        let replacement = match rest[i + 2..].chars().next() {
+            Some('=') => '#',
+            Some('(') => '[',
+            Some('/') => '\\',
+            Some(')') => ']',
+            Some('\'') => '^',
+            Some('<') => '{',
+            Some('!') => '|',
+            Some('>') => '}',
+            Some('-') => '~',
+            _ => {
+                ret.push_str(&rest[..i + 1]);
+                rest = &rest[i + 1..];
+                continue;
             }
-            Some(&'<') => {
-                iter.next();
-                match iter.peek() {
-                    Some(&'=') => {
-                        tokens.push(Token::LessThanOrEqual);
-                        iter.next();
-                    }
-                    _ => {
-                        tokens.push(Token::LessThan);
+        };
+        ret.push_str(&rest[..i]);
+        ret.push(replacement);
+        rest = &rest[i + 3..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+// Reports an error at byte `offset` of `s`, which is read from `file` if known, showing
+// the offending line.
+fn error_at(s: &str, file: Option<&str>, offset: usize, message: &str) -> ! {
+    let line_start = s[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = s[line_start..].lines().next().unwrap_or("");
+    let line_number = s[..line_start].matches('\n').count() + 1;
+    let column = s[line_start..offset].chars().count();
+    panic!(
+        "{}{}:{}: {}\n{}\n{}^",
+        file.map_or(String::new(), |file| format!("{}:", file)),
+        line_number,
+        column + 1,
+        message,
+        line,
+        " ".repeat(column)
+    );
+}
+
+// Reads the escape sequence that starts after the backslash at `pos - 1` in `s`, leaving
+// `pos` after it, and returns its code.
+fn escape_sequence(s: &[u8], pos: &mut usize) -> Result<u32, String> {
+    let digit = |c: Option<&u8>, radix| c.and_then(|&c| (c as char).to_digit(radix));
+    let c = s.get(*pos).copied();
+    *pos += 1;
+    match c {
+        Some(c @ b'0'..=b'7') => {
+            let mut v = (c - b'0') as u32;
+            for _ in 0..2 {
+                match digit(s.get(*pos), 8) {
+                    Some(d) => {
+                        v = v * 8 + d;
+                        *pos += 1;
                     }
+                    None => break,
                 }
             }
-            Some(&';') => {
-                tokens.push(Token::SemiColon);
-                iter.next();
+            if v > 0xff {
+                return Err(format!("octal escape sequence out of range: \\{:o}", v));
             }
-            Some(&'{') => {
-                tokens.push(Token::LeftBrace);
-                iter.next();
+            Ok(v)
+        }
+        Some(b'x') => {
+            let mut v: u32 = 0;
+            let mut digits = 0;
+            while let Some(d) = digit(s.get(*pos), 16) {
+                v = v.saturating_mul(16).saturating_add(d);
+                digits += 1;
+                *pos += 1;
             }
-            Some(&'}') => {
-                tokens.push(Token::RightBrace);
-                iter.next();
+            if digits == 0 {
+                return Err("\\x used with no following hex digits".to_string());
             }
-            Some(&c) => {
-                panic!("unexpected character: {}", c);
-            }
-            None => {
-                tokens.push(Token::EOF);
-                break;
+            if v > 0xff {
+                return Err("hex escape sequence out of range".to_string());
             }
+            Ok(v)
+        }
+        Some(b'a') => Ok(0x07),
+        Some(b'b') => Ok(0x08),
+        Some(b't') => Ok(0x09),
+        Some(b'n') => Ok(0x0a),
+        Some(b'v') => Ok(0x0b),
+        Some(b'f') => Ok(0x0c),
+        Some(b'r') => Ok(0x0d),
+        // \e (ASCII escape) is a GNU extension.
+        Some(b'e') => Ok(0x1b),
+        Some(c @ (b'\'' | b'"' | b'?' | b'\\')) => Ok(c as u32),
+        Some(_) => {
+            let c = String::from_utf8_lossy(&s[*pos - 1..])
+                .chars()
+                .next()
+                .unwrap();
+            Err(format!("unknown escape sequence: \\{}", c))
+        }
+        None => Err("unexpected EOF".to_string()),
+    }
+}
+
+// Converts a character constant such as `'a'` or `'\n'`, quotes included, into its value.
+pub fn convert_character_constant(s: &str) -> Result<i64, String> {
+    let body = &s.as_bytes()[1..s.len() - 1];
+    let mut pos = 1;
+    let c = match body.first() {
+        None => return Err("empty character literal".to_string()),
+        Some(b'\\') => escape_sequence(body, &mut pos)?,
+        Some(&c) if c.is_ascii() => c as u32,
+        Some(_) => return Err(format!("non-ASCII character literal: {}", s)),
+    };
+    if pos != body.len() {
+        return Err(format!("multi-character character literal: {}", s));
+    }
+    // A character constant has type int but holds a (signed) char value.
+    Ok(c as u8 as i8 as i64)
+}
+
+// Converts a string literal such as `"a\n"`, quotes included, into its bytes, without
+// the terminating NUL.
+pub fn convert_string_literal(s: &str) -> Result<Vec<u8>, String> {
+    let body = &s.as_bytes()[1..s.len() - 1];
+    let mut bytes = Vec::new();
+    let mut pos = 0;
+    while let Some(&c) = body.get(pos) {
+        pos += 1;
+        if c == b'\\' {
+            bytes.push(escape_sequence(body, &mut pos)? as u8);
+        } else {
+            bytes.push(c);
+        }
+    }
+    Ok(bytes)
+}
+
+// Converts an integer literal such as `0x1fUL` or `1'000` into its value and type,
+// following the rules of C11 6.4.4.1 for an LP64 target. The value keeps the bit pattern
+// of unsigned constants that do not fit into an i64.
+pub fn convert_integer_literal(s: &str) -> Result<(i64, IntegerType), String> {
+    let lower = s.to_ascii_lowercase();
+    let (radix, body) = if lower.starts_with("0x") {
+        (16, &s[2..])
+    } else if lower.starts_with("0b") {
+        (2, &s[2..])
+    } else if s.starts_with('0') && s.len() > 1 {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    let suffix_start = body
+        .find(|c: char| c != '\'' && !c.is_digit(radix))
+        .unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(suffix_start);
+    if digits.is_empty() && radix != 8 {
+        return Err(format!("invalid integer literal: {}", s));
+    }
+    if digits.starts_with('\'') || digits.ends_with('\'') || digits.contains("''") {
+        return Err(format!("invalid digit separator in integer literal: {}", s));
+    }
+    let (unsigned, long) = match suffix {
+        "" => (false, 0),
+        "u" | "U" => (true, 0),
+        "l" | "L" => (false, 1),
+        "ll" | "LL" => (false, 2),
+        "ul" | "uL" | "Ul" | "UL" | "lu" | "lU" | "Lu" | "LU" => (true, 1),
+        "ull" | "uLL" | "Ull" | "ULL" | "llu" | "llU" | "LLu" | "LLU" => (true, 2),
+        _ => {
+            return Err(format!(
+                "invalid suffix \"{}\" on integer literal: {}",
+                suffix, s
+            ))
+        }
+    };
+    let digits = digits.replace('\'', "");
+    let v = if digits.is_empty() {
+        0
+    } else {
+        match u64::from_str_radix(&digits, radix) {
+            Ok(v) => v,
+            Err(_) => return Err(format!("integer literal is too large: {}", s)),
+        }
+    };
+    let candidates: &[IntegerType] = match (unsigned, long, radix == 10) {
+        (false, 0, true) => &[IntegerType::Int, IntegerType::Long, IntegerType::LongLong],
+        (false, 0, false) => &[
+            IntegerType::Int,
+            IntegerType::UnsignedInt,
+            IntegerType::Long,
+            IntegerType::UnsignedLong,
+            IntegerType::LongLong,
+            IntegerType::UnsignedLongLong,
+        ],
+        (true, 0, _) => &[
+            IntegerType::UnsignedInt,
+            IntegerType::UnsignedLong,
+            IntegerType::UnsignedLongLong,
+        ],
+        (false, 1, true) => &[IntegerType::Long, IntegerType::LongLong],
+        (false, 1, false) => &[
+            IntegerType::Long,
+            IntegerType::UnsignedLong,
+            IntegerType::LongLong,
+            IntegerType::UnsignedLongLong,
+        ],
+        (true, 1, _) => &[IntegerType::UnsignedLong, IntegerType::UnsignedLongLong],
+        (false, _, true) => &[IntegerType::LongLong],
+        (false, _, false) => &[IntegerType::LongLong, IntegerType::UnsignedLongLong],
+        (true, _, _) => &[IntegerType::UnsignedLongLong],
+    };
+    match candidates.iter().find(|ty| v <= ty.max_value()) {
+        Some(ty) => Ok((v as i64, *ty)),
+        None => Err(format!("integer literal is too large for its type: {}", s)),
+    }
+}
+
+impl IntegerType {
+    pub fn is_unsigned(self) -> bool {
+        matches!(
+            self,
+            IntegerType::UnsignedInt | IntegerType::UnsignedLong | IntegerType::UnsignedLongLong
+        )
+    }
+    fn max_value(&self) -> u64 {
+        match self {
+            IntegerType::Int => i32::MAX as u64,
+            IntegerType::UnsignedInt => u32::MAX as u64,
+            IntegerType::Long | IntegerType::LongLong => i64::MAX as u64,
+            IntegerType::UnsignedLong | IntegerType::UnsignedLongLong => u64::MAX,
         }
     }
-    tokens
 }
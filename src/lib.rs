@@ -1,3 +1,7 @@
+pub mod constant;
 pub mod generator;
 pub mod lexer;
 pub mod parser;
+pub mod preprocess;
+pub mod sema;
+pub mod symbol;
@@ -1,21 +1,90 @@
-use std::{env, process};
+use std::{env, path::Path, process};
 
-use rust_9cc::{generator::Generator, lexer::tokenize, parser::Parser};
+use rust_9cc::{
+    generator::Generator,
+    lexer::{replace_trigraphs, Lexer},
+    parser::Parser,
+    preprocess::Preprocessor,
+    sema::Sema,
+};
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
-    if args.len() != 2 {
+    let mut preprocessor = Preprocessor::new();
+    let mut source = None;
+    let mut preprocess_only = false;
+    let mut trigraphs = false;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if let Some(v) = arg.strip_prefix("-D") {
+            let v = option_value(v, &mut iter, arg);
+            match v.split_once('=') {
+                Some((name, value)) => preprocessor.define(name, value),
+                None => preprocessor.define(v, "1"),
+            }
+        } else if let Some(v) = arg.strip_prefix("-U") {
+            preprocessor.undef(option_value(v, &mut iter, arg));
+        } else if let Some(v) = arg.strip_prefix("-I") {
+            preprocessor.add_include_path(option_value(v, &mut iter, arg));
+        } else if arg == "-Wunknown-pragmas" {
+            preprocessor.enable_unknown_pragma_warnings();
+        } else if arg == "-trigraphs" {
+            preprocessor.enable_trigraphs();
+            trigraphs = true;
+        } else if arg == "-E" {
+            preprocess_only = true;
+        } else if source.is_none() {
+            source = Some(arg);
+        } else {
+            eprintln!("Invalid number of arguments");
+            process::exit(1);
+        }
+    }
+    let Some(source) = source else {
         eprintln!("Invalid number of arguments");
         process::exit(1);
+    };
+    // The program is given either as a file name or as the source text itself.
+    let tokens = if Path::new(source).is_file() {
+        preprocessor.preprocess_file(Path::new(source))
+    } else if trigraphs {
+        preprocessor.preprocess(Lexer::new(&replace_trigraphs(source)))
+    } else {
+        preprocessor.preprocess(Lexer::new(source))
+    };
+    if preprocess_only {
+        print!("{}", preprocessor.to_text());
+        return;
     }
-    let tokens = tokenize(&args[1]);
-    let mut parser = Parser::new(tokens);
-    let asts = parser.program();
+    let mut parser = Parser::with_locations(tokens, preprocessor.locations());
+    let nodes = parser.program();
+    let mut sema = Sema::new();
+    sema.analyze(parser.ast_mut(), &nodes);
     let mut generator = Generator::new();
     generator.prologue();
-    generator.allocate_local_variables(parser.local_variable_count());
-    for ast in asts {
-        generator.gen(ast);
+    generator.allocate_local_variables(sema.local_variable_count());
+    for node in nodes {
+        generator.gen(parser.ast(), node);
     }
     generator.epilogue();
+    generator.data(sema.global_variables());
+    generator.string_literals();
+}
+
+// Returns the value of an option given either as `-Xvalue` or as `-X value`.
+fn option_value<'a>(
+    attached: &'a str,
+    iter: &mut impl Iterator<Item = &'a String>,
+    option: &str,
+) -> &'a str {
+    if !attached.is_empty() {
+        return attached;
+    }
+    match iter.next() {
+        Some(v) => v,
+        None => {
+            eprintln!("Missing argument to {}", option);
+            process::exit(1);
+        }
+    }
 }
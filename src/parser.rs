@@ -1,304 +1,868 @@
-use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
 
-use crate::lexer::{Keyword, Token};
+use crate::{
+    lexer::{
+        convert_character_constant, convert_integer_literal, convert_string_literal, IntegerType,
+        Keyword, Token,
+    },
+    preprocess::Location,
+    symbol::Symbol,
+};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+// Refers to a node in an `Ast`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AST {
+    UnaryOperation(UnaryOperationAST),
     BinaryOperation(BinaryOperationAST),
-    Return(Box<AST>),
+    // `lhs op= rhs`, where `op` is the operator applied before storing. Unlike
+    // `lhs = lhs op rhs`, it evaluates `lhs` only once.
+    CompoundAssignment(BinaryOperationAST),
+    Return(NodeId),
     If(IfAST),
     While(WhileAST),
     For(ForAST),
-    NumberLiteral(i64),
-    LocalVariable(LocalVariableAST),
-    Block(Vec<AST>),
+    NumberLiteral(NumberLiteralAST),
+    StringLiteral(StringLiteralAST),
+    // A use of a variable. What it refers to is recorded in `Ast::symbols`.
+    Identifier(Symbol),
+    Conditional(ConditionalAST),
+    Block(Vec<NodeId>),
+    // Declares a variable. The variable is recorded in `Ast::symbols`.
+    Declaration(DeclarationAST),
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnaryOperationAST {
+    pub op: UnaryOperator,
+    pub operand: NodeId,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum UnaryOperator {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+    Deref,
+    AddrOf,
+    // `++x` and `--x`, which give the new value, and `x++` and `x--`, which give the old
+    // one.
+    PreIncrement,
+    PreDecrement,
+    PostIncrement,
+    PostDecrement,
+    // Its operand is only looked at for its type, and never evaluated.
+    Sizeof,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BinaryOperationAST {
     pub op: BinaryOperator,
-    pub lhs: Box<AST>,
-    pub rhs: Box<AST>,
+    pub lhs: NodeId,
+    pub rhs: NodeId,
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum BinaryOperator {
     Add,
     Sub,
     Multiply,
     Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    BitAnd,
+    BitXor,
+    BitOr,
+    LogicalAnd,
+    LogicalOr,
     Equal,
     NotEqual,
     LessThan,
     LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
     Assign,
+    Comma,
+}
+// An integer or character constant with the type C gives it. `value` keeps the bit
+// pattern of unsigned constants that do not fit in an i64.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct NumberLiteralAST {
+    pub value: i64,
+    pub type_: IntegerType,
 }
+// The bytes of a string literal, after adjacent literals are joined and escapes are
+// converted, without the terminating NUL.
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct StringLiteralAST {
+    pub bytes: Vec<u8>,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct LocalVariableAST {
-    pub name: String,
+    pub name: Symbol,
     pub offset: i64,
+    pub qualifiers: Qualifiers,
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct GlobalVariableAST {
+    pub name: Symbol,
+    pub label: Symbol,
+    pub qualifiers: Qualifiers,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct IfAST {
-    pub condition: Box<AST>,
-    pub then: Box<AST>,
-    pub else_: Option<Box<AST>>,
+    pub condition: NodeId,
+    pub then: NodeId,
+    pub else_: Option<NodeId>,
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+// `condition ? then : else_`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ConditionalAST {
+    pub condition: NodeId,
+    pub then: NodeId,
+    pub else_: NodeId,
+}
+// `specifiers name = init;`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DeclarationAST {
+    pub name: Symbol,
+    pub specifiers: DeclarationSpecifiers,
+    pub init: Option<NodeId>,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct WhileAST {
-    pub condition: Box<AST>,
-    pub body: Box<AST>,
+    pub condition: NodeId,
+    pub body: NodeId,
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ForAST {
-    pub init: Option<Box<AST>>,
-    pub condition: Option<Box<AST>>,
-    pub update: Option<Box<AST>>,
-    pub body: Box<AST>,
+    pub init: Option<NodeId>,
+    pub condition: Option<NodeId>,
+    pub update: Option<NodeId>,
+    pub body: NodeId,
+}
+
+// The variable an identifier resolves to.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Variable {
+    Local(LocalVariableAST),
+    Global(GlobalVariableAST),
+}
+
+impl Variable {
+    pub fn name(&self) -> Symbol {
+        match self {
+            Variable::Local(v) => v.name,
+            Variable::Global(v) => v.name,
+        }
+    }
+    pub fn qualifiers(&self) -> Qualifiers {
+        match self {
+            Variable::Local(v) => v.qualifiers,
+            Variable::Global(v) => v.qualifiers,
+        }
+    }
+}
+
+// The type of an expression or variable, with its qualifiers, so that `&k` on a
+// `const k` gives a pointer to const.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Type {
+    pub kind: TypeKind,
+    pub qualifiers: Qualifiers,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum TypeKind {
+    Char,
+    Long,
+    // Every unsigned integer constant, which is 64 bits wide like the constants the
+    // evaluator works with.
+    UnsignedLong,
+    Pointer(Box<Type>),
+}
+
+impl Type {
+    pub fn new(kind: TypeKind) -> Self {
+        Type {
+            kind,
+            qualifiers: Qualifiers::default(),
+        }
+    }
+    pub fn pointer_to(pointee: Type) -> Self {
+        Type::new(TypeKind::Pointer(Box::new(pointee)))
+    }
+    // The type the value of an lvalue of this type has, which drops the qualifiers.
+    pub fn unqualified(&self) -> Self {
+        Type::new(self.kind.clone())
+    }
+    pub fn is_unsigned(&self) -> bool {
+        self.kind == TypeKind::UnsignedLong
+    }
+    // The number of bytes a value of this type takes.
+    pub fn size(&self) -> i64 {
+        match self.kind {
+            TypeKind::Char => 1,
+            TypeKind::Long | TypeKind::UnsignedLong | TypeKind::Pointer(_) => 8,
+        }
+    }
+    pub fn pointee(&self) -> Option<&Type> {
+        match &self.kind {
+            TypeKind::Pointer(pointee) => Some(pointee),
+            _ => None,
+        }
+    }
+}
+
+// Data about nodes that is kept outside of them, so that a pass can add its results
+// without rebuilding the tree.
+#[derive(Debug, Clone)]
+pub struct SideTable<T> {
+    values: Vec<Option<T>>,
+}
+
+impl<T> Default for SideTable<T> {
+    fn default() -> Self {
+        SideTable { values: Vec::new() }
+    }
+}
+
+impl<T> SideTable<T> {
+    pub fn get(&self, id: NodeId) -> Option<&T> {
+        self.values.get(id.0 as usize)?.as_ref()
+    }
+    pub fn insert(&mut self, id: NodeId, value: T) {
+        let i = id.0 as usize;
+        if self.values.len() <= i {
+            self.values.resize_with(i + 1, || None);
+        }
+        self.values[i] = Some(value);
+    }
+}
+
+// The nodes of a program, which refer to each other by `NodeId`, and what is known
+// about them.
+#[derive(Debug, Default)]
+pub struct Ast {
+    nodes: Vec<AST>,
+    // Where each node starts in the source.
+    pub spans: SideTable<Location>,
+    // The type of each expression.
+    pub types: SideTable<Type>,
+    // The variable each identifier refers to.
+    pub symbols: SideTable<Variable>,
+}
+
+impl Ast {
+    pub fn push(&mut self, node: AST) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+}
+
+impl Index<NodeId> for Ast {
+    type Output = AST;
+
+    fn index(&self, id: NodeId) -> &AST {
+        &self.nodes[id.0 as usize]
+    }
+}
+
+impl IndexMut<NodeId> for Ast {
+    fn index_mut(&mut self, id: NodeId) -> &mut AST {
+        &mut self.nodes[id.0 as usize]
+    }
+}
+
+// Walks a tree without changing it. `visit` dispatches on the kind of node, and every
+// `visit_*` method visits the children by default, so an implementation only overrides
+// the nodes it is interested in and calls the matching `walk_*` to keep descending.
+pub trait Visitor {
+    fn visit(&mut self, ast: &Ast, id: NodeId) {
+        match &ast[id] {
+            AST::UnaryOperation(node) => self.visit_unary_operation(ast, id, node),
+            AST::BinaryOperation(node) => self.visit_binary_operation(ast, id, node),
+            AST::CompoundAssignment(node) => self.visit_compound_assignment(ast, id, node),
+            AST::Return(value) => self.visit_return(ast, id, *value),
+            AST::If(node) => self.visit_if(ast, id, node),
+            AST::While(node) => self.visit_while(ast, id, node),
+            AST::For(node) => self.visit_for(ast, id, node),
+            AST::NumberLiteral(node) => self.visit_number_literal(ast, id, node),
+            AST::StringLiteral(node) => self.visit_string_literal(ast, id, node),
+            AST::Identifier(name) => self.visit_identifier(ast, id, *name),
+            AST::Conditional(node) => self.visit_conditional(ast, id, node),
+            AST::Block(nodes) => self.visit_block(ast, id, nodes),
+            AST::Declaration(node) => self.visit_declaration(ast, id, node),
+        }
+    }
+    fn visit_unary_operation(&mut self, ast: &Ast, _id: NodeId, node: &UnaryOperationAST) {
+        walk_unary_operation(self, ast, node);
+    }
+    fn visit_binary_operation(&mut self, ast: &Ast, _id: NodeId, node: &BinaryOperationAST) {
+        walk_binary_operation(self, ast, node);
+    }
+    fn visit_compound_assignment(&mut self, ast: &Ast, _id: NodeId, node: &BinaryOperationAST) {
+        walk_binary_operation(self, ast, node);
+    }
+    fn visit_return(&mut self, ast: &Ast, _id: NodeId, value: NodeId) {
+        walk_return(self, ast, value);
+    }
+    fn visit_if(&mut self, ast: &Ast, _id: NodeId, node: &IfAST) {
+        walk_if(self, ast, node);
+    }
+    fn visit_while(&mut self, ast: &Ast, _id: NodeId, node: &WhileAST) {
+        walk_while(self, ast, node);
+    }
+    fn visit_for(&mut self, ast: &Ast, _id: NodeId, node: &ForAST) {
+        walk_for(self, ast, node);
+    }
+    fn visit_number_literal(&mut self, _ast: &Ast, _id: NodeId, _node: &NumberLiteralAST) {}
+    fn visit_string_literal(&mut self, _ast: &Ast, _id: NodeId, _node: &StringLiteralAST) {}
+    fn visit_identifier(&mut self, _ast: &Ast, _id: NodeId, _name: Symbol) {}
+    fn visit_conditional(&mut self, ast: &Ast, _id: NodeId, node: &ConditionalAST) {
+        walk_conditional(self, ast, node);
+    }
+    fn visit_block(&mut self, ast: &Ast, _id: NodeId, nodes: &[NodeId]) {
+        walk_block(self, ast, nodes);
+    }
+    fn visit_declaration(&mut self, ast: &Ast, _id: NodeId, node: &DeclarationAST) {
+        walk_declaration(self, ast, node);
+    }
+}
+
+pub fn walk_unary_operation<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    ast: &Ast,
+    node: &UnaryOperationAST,
+) {
+    visitor.visit(ast, node.operand);
+}
+
+pub fn walk_binary_operation<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    ast: &Ast,
+    node: &BinaryOperationAST,
+) {
+    visitor.visit(ast, node.lhs);
+    visitor.visit(ast, node.rhs);
+}
+
+pub fn walk_return<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, value: NodeId) {
+    visitor.visit(ast, value);
+}
+
+pub fn walk_if<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, node: &IfAST) {
+    visitor.visit(ast, node.condition);
+    visitor.visit(ast, node.then);
+    if let Some(else_) = node.else_ {
+        visitor.visit(ast, else_);
+    }
+}
+
+pub fn walk_while<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, node: &WhileAST) {
+    visitor.visit(ast, node.condition);
+    visitor.visit(ast, node.body);
+}
+
+pub fn walk_for<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, node: &ForAST) {
+    for child in [node.init, node.condition, node.update]
+        .into_iter()
+        .flatten()
+    {
+        visitor.visit(ast, child);
+    }
+    visitor.visit(ast, node.body);
+}
+
+pub fn walk_conditional<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, node: &ConditionalAST) {
+    visitor.visit(ast, node.condition);
+    visitor.visit(ast, node.then);
+    visitor.visit(ast, node.else_);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, nodes: &[NodeId]) {
+    for &node in nodes {
+        visitor.visit(ast, node);
+    }
+}
+
+pub fn walk_declaration<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, node: &DeclarationAST) {
+    if let Some(init) = node.init {
+        visitor.visit(ast, init);
+    }
+}
+
+// Rewrites a tree in place. Each `fold_*` method returns the node that takes the place
+// of `id`: by default the children are folded and `id` itself is kept, and an
+// implementation may instead return another node, or one it has pushed to `ast`. The
+// side tables are keyed by node, so a node that is kept keeps its entries, while a
+// pushed node has none until a pass such as sema runs again.
+pub trait Folder {
+    fn fold(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        match &ast[id] {
+            AST::UnaryOperation(_) => self.fold_unary_operation(ast, id),
+            AST::BinaryOperation(_) => self.fold_binary_operation(ast, id),
+            AST::CompoundAssignment(_) => self.fold_compound_assignment(ast, id),
+            AST::Return(_) => self.fold_return(ast, id),
+            AST::If(_) => self.fold_if(ast, id),
+            AST::While(_) => self.fold_while(ast, id),
+            AST::For(_) => self.fold_for(ast, id),
+            AST::NumberLiteral(_) => self.fold_number_literal(ast, id),
+            AST::StringLiteral(_) => self.fold_string_literal(ast, id),
+            AST::Identifier(_) => self.fold_identifier(ast, id),
+            AST::Conditional(_) => self.fold_conditional(ast, id),
+            AST::Block(_) => self.fold_block(ast, id),
+            AST::Declaration(_) => self.fold_declaration(ast, id),
+        }
+    }
+    fn fold_unary_operation(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_binary_operation(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_compound_assignment(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_return(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_if(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_while(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_for(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_number_literal(&mut self, _ast: &mut Ast, id: NodeId) -> NodeId {
+        id
+    }
+    fn fold_string_literal(&mut self, _ast: &mut Ast, id: NodeId) -> NodeId {
+        id
+    }
+    fn fold_identifier(&mut self, _ast: &mut Ast, id: NodeId) -> NodeId {
+        id
+    }
+    fn fold_conditional(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_block(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+    fn fold_declaration(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        fold_children(self, ast, id)
+    }
+}
+
+// Folds every child of `id` and links the results into it in place of the old children.
+pub fn fold_children<F: Folder + ?Sized>(folder: &mut F, ast: &mut Ast, id: NodeId) -> NodeId {
+    let mut node = ast[id].clone();
+    let mut fold = |child: &mut NodeId| *child = folder.fold(ast, *child);
+    match &mut node {
+        AST::UnaryOperation(node) => fold(&mut node.operand),
+        AST::BinaryOperation(node) | AST::CompoundAssignment(node) => {
+            fold(&mut node.lhs);
+            fold(&mut node.rhs);
+        }
+        AST::Return(value) => fold(value),
+        AST::If(node) => {
+            fold(&mut node.condition);
+            fold(&mut node.then);
+            if let Some(else_) = &mut node.else_ {
+                fold(else_);
+            }
+        }
+        AST::While(node) => {
+            fold(&mut node.condition);
+            fold(&mut node.body);
+        }
+        AST::For(node) => {
+            for child in [&mut node.init, &mut node.condition, &mut node.update]
+                .into_iter()
+                .flatten()
+            {
+                fold(child);
+            }
+            fold(&mut node.body);
+        }
+        AST::Conditional(node) => {
+            fold(&mut node.condition);
+            fold(&mut node.then);
+            fold(&mut node.else_);
+        }
+        AST::Block(nodes) => nodes.iter_mut().for_each(fold),
+        AST::Declaration(node) => {
+            if let Some(init) = &mut node.init {
+                fold(init);
+            }
+        }
+        AST::NumberLiteral(_) | AST::StringLiteral(_) | AST::Identifier(_) => {}
+    }
+    ast[id] = node;
+    id
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Infix {
+    Binary(BinaryOperator),
+    // `=`, or a compound assignment such as `+=` with its operator.
+    Assign(Option<BinaryOperator>),
+    Conditional,
+}
+
+// The infix operators with their precedence level, numbered as in the usual C operator
+// table: 1 and 2 are the postfix and unary operators, and a higher level binds looser.
+#[rustfmt::skip]
+const INFIX_OPERATORS: &[(Token, u8, Associativity, Infix)] = &[
+    (Token::Asterisk, 3, Associativity::Left, Infix::Binary(BinaryOperator::Multiply)),
+    (Token::Slash, 3, Associativity::Left, Infix::Binary(BinaryOperator::Divide)),
+    (Token::Percent, 3, Associativity::Left, Infix::Binary(BinaryOperator::Remainder)),
+    (Token::Plus, 4, Associativity::Left, Infix::Binary(BinaryOperator::Add)),
+    (Token::Minus, 4, Associativity::Left, Infix::Binary(BinaryOperator::Sub)),
+    (Token::LeftShift, 5, Associativity::Left, Infix::Binary(BinaryOperator::ShiftLeft)),
+    (Token::RightShift, 5, Associativity::Left, Infix::Binary(BinaryOperator::ShiftRight)),
+    (Token::LessThan, 6, Associativity::Left, Infix::Binary(BinaryOperator::LessThan)),
+    (Token::LessThanOrEqual, 6, Associativity::Left, Infix::Binary(BinaryOperator::LessThanOrEqual)),
+    (Token::GreaterThan, 6, Associativity::Left, Infix::Binary(BinaryOperator::GreaterThan)),
+    (Token::GreaterThanOrEqual, 6, Associativity::Left, Infix::Binary(BinaryOperator::GreaterThanOrEqual)),
+    (Token::Equal, 7, Associativity::Left, Infix::Binary(BinaryOperator::Equal)),
+    (Token::NotEqual, 7, Associativity::Left, Infix::Binary(BinaryOperator::NotEqual)),
+    (Token::Ampersand, 8, Associativity::Left, Infix::Binary(BinaryOperator::BitAnd)),
+    (Token::Caret, 9, Associativity::Left, Infix::Binary(BinaryOperator::BitXor)),
+    (Token::Pipe, 10, Associativity::Left, Infix::Binary(BinaryOperator::BitOr)),
+    (Token::LogicalAnd, 11, Associativity::Left, Infix::Binary(BinaryOperator::LogicalAnd)),
+    (Token::LogicalOr, 12, Associativity::Left, Infix::Binary(BinaryOperator::LogicalOr)),
+    (Token::Question, 13, Associativity::Right, Infix::Conditional),
+    (Token::Assign, 14, Associativity::Right, Infix::Assign(None)),
+    (Token::MultiplyAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::Multiply))),
+    (Token::DivideAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::Divide))),
+    (Token::ModuloAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::Remainder))),
+    (Token::AddAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::Add))),
+    (Token::SubtractAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::Sub))),
+    (Token::LeftShiftAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::ShiftLeft))),
+    (Token::RightShiftAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::ShiftRight))),
+    (Token::AndAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::BitAnd))),
+    (Token::XorAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::BitXor))),
+    (Token::OrAssign, 14, Associativity::Right, Infix::Assign(Some(BinaryOperator::BitOr))),
+    (Token::Comma, 15, Associativity::Left, Infix::Binary(BinaryOperator::Comma)),
+];
+const CONDITIONAL_LEVEL: u8 = 13;
+const ASSIGNMENT_LEVEL: u8 = 14;
+const COMMA_LEVEL: u8 = 15;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum StorageClass {
+    Static,
+    Extern,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Qualifiers {
+    pub const_: bool,
+    pub volatile: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct DeclarationSpecifiers {
+    pub storage_class: Option<StorageClass>,
+    pub qualifiers: Qualifiers,
+}
+
+// A variable with static storage duration that has to be emitted into the data section.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct GlobalVariable {
+    pub label: Symbol,
+    pub init: Option<i64>,
+    pub qualifiers: Qualifiers,
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
+    // Where each token comes from, if known, for diagnostics.
+    locations: Vec<Location>,
     cursor: usize,
-    local_variable_map: HashMap<String, i64>,
-    local_variable_current_offset: i64,
+    ast: Ast,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
+        Self::with_locations(tokens, Vec::new())
+    }
+    pub fn with_locations(tokens: Vec<Token>, locations: Vec<Location>) -> Self {
         Parser {
             tokens,
+            locations,
             cursor: 0,
-            local_variable_map: HashMap::new(),
-            local_variable_current_offset: 8,
+            ast: Ast::default(),
         }
     }
-    pub fn program(&mut self) -> Vec<AST> {
+    pub fn program(&mut self) -> Vec<NodeId> {
         let mut nodes = Vec::new();
         while self.tokens[self.cursor] != Token::EOF {
             nodes.push(self.stmt());
         }
         nodes
     }
-    pub fn local_variable_count(&self) -> usize {
-        self.local_variable_map.len()
+    // Parses the whole input as a single expression without assignments, as used by
+    // `#if` in the preprocessor.
+    pub fn constant_expression(&mut self) -> NodeId {
+        let node = self.expression(CONDITIONAL_LEVEL);
+        if self.tokens[self.cursor] != Token::EOF {
+            self.error(&format!("unexpected token: {:?}", self.tokens[self.cursor]));
+        }
+        node
+    }
+    pub fn ast(&self) -> &Ast {
+        &self.ast
     }
-    fn stmt(&mut self) -> AST {
+    pub fn ast_mut(&mut self) -> &mut Ast {
+        &mut self.ast
+    }
+    fn stmt(&mut self) -> NodeId {
+        let start = self.cursor;
         if self.consume(Token::LeftBrace) {
             let mut nodes = Vec::new();
             while !self.consume(Token::RightBrace) {
                 nodes.push(self.stmt());
             }
-            return AST::Block(nodes);
+            return self.new_node(start, AST::Block(nodes));
+        }
+        if let Some(specifiers) = self.declaration_specifiers() {
+            return self.declaration(start, specifiers);
         }
         if self.consume(Token::Keyword(Keyword::Return)) {
-            let node = AST::Return(Box::new(self.expr()));
+            let value = self.expr();
             self.expect(Token::SemiColon);
-            return node;
+            return self.new_node(start, AST::Return(value));
         }
         if self.consume(Token::Keyword(Keyword::If)) {
             self.expect(Token::LeftParen);
-            let condition = Box::new(self.expr());
+            let condition = self.expr();
             self.expect(Token::RightParen);
-            let then = Box::new(self.stmt());
+            let then = self.stmt();
             let else_ = if self.consume(Token::Keyword(Keyword::Else)) {
-                Some(Box::new(self.stmt()))
+                Some(self.stmt())
             } else {
                 None
             };
-            return AST::If(IfAST {
-                condition,
-                then,
-                else_,
-            });
+            return self.new_node(
+                start,
+                AST::If(IfAST {
+                    condition,
+                    then,
+                    else_,
+                }),
+            );
         }
         if self.consume(Token::Keyword(Keyword::While)) {
             self.expect(Token::LeftParen);
-            let condition = Box::new(self.expr());
+            let condition = self.expr();
             self.expect(Token::RightParen);
-            let body = Box::new(self.stmt());
-            return AST::While(WhileAST { condition, body });
+            let body = self.stmt();
+            return self.new_node(start, AST::While(WhileAST { condition, body }));
         }
         if self.consume(Token::Keyword(Keyword::For)) {
             self.expect(Token::LeftParen);
             let init = if self.consume(Token::SemiColon) {
                 None
             } else {
-                let node = Some(Box::new(self.expr()));
+                let node = Some(self.expr());
                 self.expect(Token::SemiColon);
                 node
             };
             let condition = if self.consume(Token::SemiColon) {
                 None
             } else {
-                let node = Some(Box::new(self.expr()));
+                let node = Some(self.expr());
                 self.expect(Token::SemiColon);
                 node
             };
             let update = if self.consume(Token::RightParen) {
                 None
             } else {
-                let node = Some(Box::new(self.expr()));
+                let node = Some(self.expr());
                 self.expect(Token::RightParen);
                 node
             };
-            let body = Box::new(self.stmt());
-            return AST::For(ForAST {
-                init,
-                condition,
-                update,
-                body,
-            });
+            let body = self.stmt();
+            return self.new_node(
+                start,
+                AST::For(ForAST {
+                    init,
+                    condition,
+                    update,
+                    body,
+                }),
+            );
         }
         let node = self.expr();
         self.expect(Token::SemiColon);
         node
     }
-    fn expr(&mut self) -> AST {
-        self.assign()
+    fn declaration(&mut self, start: usize, specifiers: DeclarationSpecifiers) -> NodeId {
+        let name = self.expect_identifier();
+        let init = if self.consume(Token::Assign) {
+            Some(self.assign())
+        } else {
+            None
+        };
+        self.expect(Token::SemiColon);
+        self.new_node(
+            start,
+            AST::Declaration(DeclarationAST {
+                name,
+                specifiers,
+                init,
+            }),
+        )
     }
-    fn assign(&mut self) -> AST {
-        let mut node = self.equality();
-        if self.consume(Token::Assign) {
-            let rhs = self.assign();
-            node = AST::BinaryOperation(BinaryOperationAST {
-                op: BinaryOperator::Assign,
-                lhs: Box::new(node),
-                rhs: Box::new(rhs),
-            });
-        }
-        node
+    fn expr(&mut self) -> NodeId {
+        self.expression(COMMA_LEVEL)
     }
-    fn equality(&mut self) -> AST {
-        let mut node = self.relational();
-        loop {
-            if self.consume(Token::Equal) {
-                let rhs = self.relational();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::Equal,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else if self.consume(Token::NotEqual) {
-                let rhs = self.relational();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::NotEqual,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else {
-                break;
-            }
-        }
-        node
+    fn assign(&mut self) -> NodeId {
+        self.expression(ASSIGNMENT_LEVEL)
     }
-    fn relational(&mut self) -> AST {
-        let mut node = self.add();
-        loop {
-            if self.consume(Token::GreaterThan) {
-                let rhs = self.add();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::LessThan,
-                    lhs: Box::new(rhs),
-                    rhs: Box::new(node),
-                });
-            } else if self.consume(Token::GreaterThanOrEqual) {
-                let rhs = self.add();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::LessThanOrEqual,
-                    lhs: Box::new(rhs),
-                    rhs: Box::new(node),
-                });
-            } else if self.consume(Token::LessThan) {
-                let rhs = self.add();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::LessThan,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else if self.consume(Token::LessThanOrEqual) {
-                let rhs = self.add();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::LessThanOrEqual,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else {
+    // Parses an expression made of operators whose level is at most `max_level`.
+    fn expression(&mut self, max_level: u8) -> NodeId {
+        let start = self.cursor;
+        let mut node = self.unary();
+        while let Some(&(_, level, associativity, infix)) = INFIX_OPERATORS
+            .iter()
+            .find(|(token, ..)| *token == self.tokens[self.cursor])
+        {
+            if level > max_level {
                 break;
             }
+            self.cursor += 1;
+            // The right operand of a left-associative operator may only hold operators
+            // that bind tighter, so that `a - b - c` groups as `(a - b) - c`.
+            let rhs_level = match associativity {
+                Associativity::Left => level - 1,
+                Associativity::Right => level,
+            };
+            node = match infix {
+                Infix::Binary(op) => {
+                    let rhs = self.expression(rhs_level);
+                    self.new_node(
+                        start,
+                        AST::BinaryOperation(BinaryOperationAST { op, lhs: node, rhs }),
+                    )
+                }
+                Infix::Assign(op) => {
+                    let rhs = self.expression(rhs_level);
+                    self.new_node(
+                        start,
+                        match op {
+                            Some(op) => {
+                                AST::CompoundAssignment(BinaryOperationAST { op, lhs: node, rhs })
+                            }
+                            None => AST::BinaryOperation(BinaryOperationAST {
+                                op: BinaryOperator::Assign,
+                                lhs: node,
+                                rhs,
+                            }),
+                        },
+                    )
+                }
+                Infix::Conditional => {
+                    let then = self.expr();
+                    self.expect(Token::Colon);
+                    let else_ = self.expression(rhs_level);
+                    self.new_node(
+                        start,
+                        AST::Conditional(ConditionalAST {
+                            condition: node,
+                            then,
+                            else_,
+                        }),
+                    )
+                }
+            };
         }
         node
     }
-    fn add(&mut self) -> AST {
-        let mut node = self.mul();
-        loop {
-            if self.consume(Token::Plus) {
-                let rhs = self.mul();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::Add,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else if self.consume(Token::Minus) {
-                let rhs = self.mul();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::Sub,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else {
-                break;
-            }
-        }
-        node
+    fn unary(&mut self) -> NodeId {
+        let start = self.cursor;
+        let op = match self.tokens[self.cursor] {
+            Token::Minus => UnaryOperator::Neg,
+            Token::Plus => UnaryOperator::Plus,
+            Token::Exclamation => UnaryOperator::Not,
+            Token::Tilde => UnaryOperator::BitNot,
+            Token::Asterisk => UnaryOperator::Deref,
+            Token::Ampersand => UnaryOperator::AddrOf,
+            Token::Increment => UnaryOperator::PreIncrement,
+            Token::Decrement => UnaryOperator::PreDecrement,
+            Token::Keyword(Keyword::Sizeof) => UnaryOperator::Sizeof,
+            _ => return self.postfix(),
+        };
+        self.cursor += 1;
+        let operand = self.unary();
+        self.new_node(
+            start,
+            AST::UnaryOperation(UnaryOperationAST { op, operand }),
+        )
     }
-    fn mul(&mut self) -> AST {
-        let mut node = self.unary();
+    fn postfix(&mut self) -> NodeId {
+        let start = self.cursor;
+        let mut node = self.primary();
         loop {
-            if self.consume(Token::Asterisk) {
-                let rhs = self.unary();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::Multiply,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
-            } else if self.consume(Token::Slash) {
-                let rhs = self.unary();
-                node = AST::BinaryOperation(BinaryOperationAST {
-                    op: BinaryOperator::Divide,
-                    lhs: Box::new(node),
-                    rhs: Box::new(rhs),
-                });
+            let op = if self.consume(Token::Increment) {
+                UnaryOperator::PostIncrement
+            } else if self.consume(Token::Decrement) {
+                UnaryOperator::PostDecrement
             } else {
-                break;
-            }
-        }
-        node
-    }
-    fn unary(&mut self) -> AST {
-        if self.consume(Token::Plus) {
-            return self.primary();
-        }
-        if self.consume(Token::Minus) {
-            let v = self.primary();
-            return AST::BinaryOperation(BinaryOperationAST {
-                op: BinaryOperator::Sub,
-                lhs: Box::new(AST::NumberLiteral(0)),
-                rhs: Box::new(v),
-            });
+                return node;
+            };
+            node = self.new_node(
+                start,
+                AST::UnaryOperation(UnaryOperationAST { op, operand: node }),
+            );
         }
-        self.primary()
     }
-    fn primary(&mut self) -> AST {
+    fn primary(&mut self) -> NodeId {
         if self.consume(Token::LeftParen) {
             let v = self.expr();
             self.expect(Token::RightParen);
             return v;
         }
-        if let Token::Number(_) = self.tokens[self.cursor] {
+        if let Token::Number(_) | Token::Character(_) = self.tokens[self.cursor] {
             return self.expect_number();
         }
-        self.expect_local_variable()
+        if let Token::String(_) = self.tokens[self.cursor] {
+            return self.expect_string();
+        }
+        if let Token::Other(c @ ('\'' | '"')) = self.tokens[self.cursor] {
+            self.error(&format!("missing terminating {} character", c));
+        }
+        self.expect_identifier_node()
     }
 
+    // Adds a node to the tree, spanning from the token at `start`.
+    fn new_node(&mut self, start: usize, node: AST) -> NodeId {
+        let id = self.ast.push(node);
+        if let Some(location) = self.locations.get(start) {
+            self.ast.spans.insert(id, location.clone());
+        }
+        id
+    }
+    // Reports an error at the current token, prefixed with its location when known.
+    fn error(&self, message: &str) -> ! {
+        match self
+            .locations
+            .get(self.cursor)
+            .or_else(|| self.locations.last())
+        {
+            Some(location) => panic!("{}: {}", location, message),
+            None => panic!("{}", message),
+        }
+    }
     fn consume(&mut self, expected: Token) -> bool {
         if self.tokens[self.cursor] != expected {
             return false;
@@ -308,37 +872,88 @@ impl Parser {
     }
     fn expect(&mut self, expected: Token) {
         if self.tokens[self.cursor] != expected {
-            panic!("unexpected token: {:?}", self.tokens[self.cursor]);
+            self.error(&format!("unexpected token: {:?}", self.tokens[self.cursor]));
         }
         self.cursor += 1;
     }
-    fn expect_number(&mut self) -> AST {
-        if let Token::Number(v) = self.tokens[self.cursor] {
-            self.cursor += 1;
-            AST::NumberLiteral(v)
+    fn declaration_specifiers(&mut self) -> Option<DeclarationSpecifiers> {
+        let mut specifiers = DeclarationSpecifiers::default();
+        let mut found = false;
+        loop {
+            let storage_class = if self.consume(Token::Keyword(Keyword::Static)) {
+                StorageClass::Static
+            } else if self.consume(Token::Keyword(Keyword::Extern)) {
+                StorageClass::Extern
+            } else if self.consume(Token::Keyword(Keyword::Const)) {
+                specifiers.qualifiers.const_ = true;
+                found = true;
+                continue;
+            } else if self.consume(Token::Keyword(Keyword::Volatile)) {
+                specifiers.qualifiers.volatile = true;
+                found = true;
+                continue;
+            } else {
+                break;
+            };
+            if specifiers.storage_class.is_some() {
+                self.error("multiple storage classes in declaration specifiers");
+            }
+            specifiers.storage_class = Some(storage_class);
+            found = true;
+        }
+        if found {
+            Some(specifiers)
         } else {
-            panic!("unexpected token: {:?}", self.tokens[self.cursor]);
+            None
         }
     }
-    fn expect_local_variable(&mut self) -> AST {
-        if let Token::Identifier(v) = &self.tokens[self.cursor] {
+    fn expect_identifier(&mut self) -> Symbol {
+        if let Token::Identifier(v) = self.tokens[self.cursor] {
             self.cursor += 1;
-            if let Some(offset) = self.local_variable_map.get(v) {
-                AST::LocalVariable(LocalVariableAST {
-                    name: v.clone(),
-                    offset: *offset,
-                })
-            } else {
-                let offset = self.local_variable_current_offset;
-                self.local_variable_map.insert(v.clone(), offset);
-                self.local_variable_current_offset += 8;
-                AST::LocalVariable(LocalVariableAST {
-                    name: v.clone(),
-                    offset,
-                })
-            }
+            v
         } else {
-            panic!("unexpected token: {:?}", self.tokens[self.cursor]);
+            self.error(&format!("unexpected token: {:?}", self.tokens[self.cursor]));
+        }
+    }
+    fn expect_number(&mut self) -> NodeId {
+        let v = match self.tokens[self.cursor] {
+            Token::Number(v) => convert_integer_literal(v.as_str()),
+            // A character constant has type int.
+            Token::Character(v) => {
+                convert_character_constant(v.as_str()).map(|v| (v, IntegerType::Int))
+            }
+            _ => self.error(&format!("unexpected token: {:?}", self.tokens[self.cursor])),
+        };
+        match v {
+            Ok((value, type_)) => {
+                self.cursor += 1;
+                self.new_node(
+                    self.cursor - 1,
+                    AST::NumberLiteral(NumberLiteralAST { value, type_ }),
+                )
+            }
+            Err(message) => self.error(&message),
         }
     }
+    fn expect_string(&mut self) -> NodeId {
+        let start = self.cursor;
+        if !matches!(self.tokens[self.cursor], Token::String(_)) {
+            self.error(&format!("unexpected token: {:?}", self.tokens[self.cursor]));
+        }
+        let mut bytes = Vec::new();
+        // Adjacent string literals are concatenated into one.
+        while let Token::String(v) = self.tokens[self.cursor] {
+            match convert_string_literal(v.as_str()) {
+                Ok(v) => bytes.extend(v),
+                Err(message) => self.error(&message),
+            }
+            self.cursor += 1;
+        }
+        self.new_node(start, AST::StringLiteral(StringLiteralAST { bytes }))
+    }
+    fn expect_identifier_node(&mut self) -> NodeId {
+        let start = self.cursor;
+        let name = self.expect_identifier();
+        self.new_node(start, AST::Identifier(name))
+    }
 }
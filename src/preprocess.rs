@@ -0,0 +1,1180 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    constant,
+    lexer::{convert_integer_literal, convert_string_literal, replace_trigraphs, Lexer, Token},
+    parser::Parser,
+    symbol::Symbol,
+};
+
+// Where a token comes from, after #line directives have been applied. Tokens produced
+// by a macro expansion are located at the macro invocation.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Location {
+    pub file: Rc<str>,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+// A token as seen by the preprocessor. Whitespace and line breaks are kept as flags on
+// the following token instead of as tokens of their own.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PPToken {
+    pub token: Token,
+    // First token on its line, so a `#` here starts a directive.
+    pub bol: bool,
+    // Preceded by whitespace.
+    pub space: bool,
+    // Names of the macros this token came out of, which must not expand it again.
+    pub hideset: HashSet<Symbol>,
+    pub location: Location,
+}
+
+impl PPToken {
+    // The spelling of an identifier. Keywords are ordinary identifiers to the
+    // preprocessor, so `#if` and `#define static` work.
+    fn name(&self) -> Option<Symbol> {
+        match self.token {
+            Token::Identifier(v) => Some(v),
+            Token::Keyword(v) => Some(Symbol::intern(v.as_str())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Macro {
+    pub name: Symbol,
+    // `Some` for a function-like macro.
+    pub params: Option<Vec<Symbol>>,
+    pub variadic: bool,
+    pub body: Vec<PPToken>,
+    // Set for the macros whose value is computed at each expansion, such as __LINE__.
+    pub builtin: Option<Builtin>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Builtin {
+    File,
+    Line,
+    Counter,
+    Date,
+    Time,
+    // Only meaningful in #if, where it is handled before macro expansion.
+    HasInclude,
+}
+
+// A #pragma (or _Pragma) the compiler understands, in source order.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Pragma {
+    pub kind: PragmaKind,
+    pub location: Location,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PragmaKind {
+    Once,
+    Pack(Pack),
+}
+
+// The forms of `#pragma pack`, where alignments are in bytes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pack {
+    // `pack(n)`, or `pack()` to restore the default.
+    Set(Option<u64>),
+    // `pack(push)` or `pack(push, n)`.
+    Push(Option<u64>),
+    Pop,
+}
+
+// Macros defined before any source is read, as gcc does for this target.
+const PREDEFINED_MACROS: [(&str, &str); 5] = [
+    ("__STDC__", "1"),
+    ("__STDC_VERSION__", "201112L"),
+    ("__STDC_HOSTED__", "1"),
+    ("__x86_64__", "1"),
+    ("__linux__", "1"),
+];
+
+// Directories searched for `#include <...>` after the ones given with -I.
+const SYSTEM_INCLUDE_PATHS: [&str; 3] = [
+    "/usr/local/include",
+    "/usr/include/x86_64-linux-gnu",
+    "/usr/include",
+];
+
+// The same limit as gcc, which mostly catches files that include themselves.
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+// One level of #if/#ifdef/#ifndef nesting.
+struct Conditional {
+    // Whether the current group is being processed.
+    active: bool,
+    // Whether some group of this conditional has been (or must not be) selected, so that
+    // later #elif and #else groups are skipped.
+    taken: bool,
+    seen_else: bool,
+}
+
+pub struct Preprocessor {
+    macros: HashMap<Symbol, Macro>,
+    include_paths: Vec<PathBuf>,
+    // The files being read, innermost last.
+    include_stack: Vec<PathBuf>,
+    pragma_once: HashSet<PathBuf>,
+    // Files wrapped in `#ifndef X ... #endif`, which need not be read again while X is
+    // defined.
+    include_guards: HashMap<PathBuf, Symbol>,
+    // The result of the last run, kept for `locations` and `to_text`.
+    output: Vec<PPToken>,
+    counter: i64,
+    pragmas: Vec<Pragma>,
+    warn_unknown_pragmas: bool,
+    trigraphs: bool,
+    // The values of __DATE__ and __TIME__, fixed when the preprocessor is created.
+    date: String,
+    time: String,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        // Honour SOURCE_DATE_EPOCH like gcc, so that builds can be reproducible.
+        let now = match env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            Some(v) => v,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        };
+        let (date, time) = format_date_time(now);
+        let mut preprocessor = Preprocessor {
+            macros: HashMap::new(),
+            include_paths: Vec::new(),
+            include_stack: Vec::new(),
+            pragma_once: HashSet::new(),
+            include_guards: HashMap::new(),
+            output: Vec::new(),
+            counter: 0,
+            pragmas: Vec::new(),
+            warn_unknown_pragmas: false,
+            trigraphs: false,
+            date,
+            time,
+        };
+        for (name, value) in PREDEFINED_MACROS {
+            preprocessor.define(name, value);
+        }
+        for (name, builtin) in [
+            ("__FILE__", Builtin::File),
+            ("__LINE__", Builtin::Line),
+            ("__COUNTER__", Builtin::Counter),
+            ("__DATE__", Builtin::Date),
+            ("__TIME__", Builtin::Time),
+            ("__has_include", Builtin::HasInclude),
+        ] {
+            preprocessor.define_macro(Macro {
+                name: Symbol::intern(name),
+                params: None,
+                variadic: false,
+                body: Vec::new(),
+                builtin: Some(builtin),
+            });
+        }
+        preprocessor
+    }
+    // Adds a directory to search for included files, as `-I dir` does.
+    pub fn add_include_path(&mut self, path: &str) {
+        self.include_paths.push(PathBuf::from(path));
+    }
+    // Defines a macro from source text, as `-D name=value` does. `name` may carry a
+    // parameter list, e.g. `MAX(a,b)`.
+    pub fn define(&mut self, name: &str, value: &str) {
+        let tokens = to_pp_tokens(
+            Lexer::new(&format!("{} {}", name, value)),
+            &Rc::from("<command line>"),
+        );
+        self.define_directive(&tokens);
+    }
+    pub fn undef(&mut self, name: &str) {
+        self.macros.remove(&Symbol::intern(name));
+    }
+    // Makes pragmas that are ignored produce a warning, as `-Wunknown-pragmas` does.
+    pub fn enable_unknown_pragma_warnings(&mut self) {
+        self.warn_unknown_pragmas = true;
+    }
+    // Replaces trigraphs in the files read from now on, as `-trigraphs` does.
+    pub fn enable_trigraphs(&mut self) {
+        self.trigraphs = true;
+    }
+    // The pragmas seen so far, in source order.
+    pub fn pragmas(&self) -> &[Pragma] {
+        &self.pragmas
+    }
+    // Preprocesses source that does not come from a file, so that quoted includes are
+    // looked up relative to the working directory.
+    pub fn preprocess(&mut self, tokens: impl IntoIterator<Item = Token>) -> Vec<Token> {
+        self.output = self.expand(to_pp_tokens(tokens, &Rc::from("<source>")), true);
+        self.tokens()
+    }
+    pub fn preprocess_file(&mut self, path: &Path) -> Vec<Token> {
+        let mut output = Vec::new();
+        self.include_file(path.to_path_buf(), &mut output);
+        self.output = output;
+        self.tokens()
+    }
+    // The location of each token returned by the last run, in the same order.
+    pub fn locations(&self) -> Vec<Location> {
+        self.output.iter().map(|t| t.location.clone()).collect()
+    }
+    // Spells the result of the last run as source text, as `-E` prints it. Tokens keep
+    // their lines, and a `# line "file"` marker is emitted wherever the output moves to
+    // another file or skips many lines. Like gcc, it separates tokens that macro expansion
+    // put next to each other when they would otherwise lex as something else.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut current: Option<&Location> = None;
+        let mut previous: Option<&Token> = None;
+        for t in &self.output {
+            match current {
+                Some(c)
+                    if c.file == t.location.file
+                        && c.line <= t.location.line
+                        && t.location.line <= c.line + 8 =>
+                {
+                    if c.line < t.location.line {
+                        text.push_str(&"\n".repeat(t.location.line - c.line));
+                    } else if t.space || previous.is_some_and(|p| needs_separator(p, &t.token)) {
+                        text.push(' ');
+                    }
+                }
+                _ => {
+                    if current.is_some() {
+                        text.push('\n');
+                    }
+                    text.push_str(&format!("# {} \"{}\"\n", t.location.line, t.location.file));
+                }
+            }
+            text.push_str(&t.token.to_string());
+            current = Some(&t.location);
+            previous = Some(&t.token);
+        }
+        text.push('\n');
+        text
+    }
+    fn tokens(&self) -> Vec<Token> {
+        let mut tokens = self
+            .output
+            .iter()
+            .map(|t| t.token.clone())
+            .collect::<Vec<Token>>();
+        tokens.push(Token::EOF);
+        tokens
+    }
+
+    // Macro-expands `input`, running directives found at the beginning of lines when
+    // `directives` is set. This is Prosser's algorithm: the tokens produced by an
+    // expansion are pushed back onto the input, so they are rescanned together with the
+    // tokens that follow, and hide-sets stop a macro from expanding inside itself.
+    fn expand(&mut self, input: Vec<PPToken>, directives: bool) -> Vec<PPToken> {
+        // The remaining input, in reverse so that the next token is at the end.
+        let mut rest = input;
+        rest.reverse();
+        let mut output = Vec::new();
+        let mut conditionals = Vec::new();
+        while let Some(t) = rest.pop() {
+            let skipping = conditionals.iter().any(|c: &Conditional| !c.active);
+            if directives && t.bol && t.token == Token::Hash {
+                let mut line = Vec::new();
+                while rest.last().is_some_and(|t| !t.bol) {
+                    line.push(rest.pop().unwrap());
+                }
+                if self.conditional_directive(&line, &mut conditionals) || skipping {
+                    continue;
+                }
+                // #line changes the location of the rest of the file, so it needs `rest`.
+                if line.first().and_then(PPToken::name).map(Symbol::as_str) == Some("line") {
+                    self.line_directive(&t, &line[1..], &mut rest);
+                } else if let Some(PPToken {
+                    token: Token::Number(..),
+                    ..
+                }) = line.first()
+                {
+                    // The `# 12 "file"` form that -E emits.
+                    self.line_directive(&t, &line, &mut rest);
+                } else {
+                    self.directive(&line, &mut output);
+                }
+                continue;
+            }
+            if skipping {
+                continue;
+            }
+            if directives && t.name().map(Symbol::as_str) == Some("_Pragma") {
+                self.pragma_operator(&t, &mut rest);
+                continue;
+            }
+            let Some(m) = self.find_macro(&t) else {
+                output.push(t);
+                continue;
+            };
+            let expanded = match &m.params {
+                None => match m.builtin {
+                    Some(builtin) => vec![self.expand_builtin(builtin, &t)],
+                    None => {
+                        let mut hideset = t.hideset.clone();
+                        hideset.insert(m.name);
+                        self.subst(&m, &[], &hideset)
+                    }
+                },
+                Some(_) => {
+                    // A function-like macro name not followed by `(` is not an invocation.
+                    if rest.last().map(|t| &t.token) != Some(&Token::LeftParen) {
+                        output.push(t);
+                        continue;
+                    }
+                    rest.pop();
+                    let (args, right_paren) = self.read_arguments(&m, &mut rest);
+                    let mut hideset = t
+                        .hideset
+                        .intersection(&right_paren.hideset)
+                        .cloned()
+                        .collect::<HashSet<Symbol>>();
+                    hideset.insert(m.name);
+                    self.subst(&m, &args, &hideset)
+                }
+            };
+            for (i, mut e) in expanded.into_iter().enumerate().rev() {
+                e.bol = false;
+                e.location = t.location.clone();
+                if i == 0 {
+                    e.space = t.space;
+                }
+                rest.push(e);
+            }
+        }
+        if !conditionals.is_empty() {
+            panic!("unterminated conditional directive");
+        }
+        output
+    }
+    fn expand_builtin(&mut self, builtin: Builtin, t: &PPToken) -> PPToken {
+        let token = match builtin {
+            Builtin::File => Token::string(t.location.file.as_bytes()),
+            Builtin::Line => Token::number(t.location.line as i64),
+            Builtin::Counter => {
+                self.counter += 1;
+                Token::number(self.counter - 1)
+            }
+            Builtin::Date => Token::string(self.date.as_bytes()),
+            Builtin::Time => Token::string(self.time.as_bytes()),
+            Builtin::HasInclude => panic!("__has_include outside of #if"),
+        };
+        PPToken { token, ..t.clone() }
+    }
+    fn find_macro(&self, t: &PPToken) -> Option<Macro> {
+        let name = t.name()?;
+        if t.hideset.contains(&name) {
+            return None;
+        }
+        self.macros.get(&name).cloned()
+    }
+    // Reads the arguments of a function-like macro invocation up to and including the
+    // closing parenthesis, which is returned as well since its hide-set is needed.
+    fn read_arguments(&self, m: &Macro, rest: &mut Vec<PPToken>) -> (Vec<Vec<PPToken>>, PPToken) {
+        let params = m.params.as_ref().unwrap();
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+        let right_paren = loop {
+            let Some(t) = rest.pop() else {
+                panic!("unterminated argument list invoking macro: {}", m.name);
+            };
+            match t.token {
+                Token::LeftParen => depth += 1,
+                Token::RightParen if depth == 0 => break t,
+                Token::RightParen => depth -= 1,
+                // The variable arguments are collected into a single one, commas included.
+                Token::Comma if depth == 0 && !(m.variadic && args.len() > params.len()) => {
+                    args.push(Vec::new());
+                    continue;
+                }
+                _ => {}
+            }
+            args.last_mut().unwrap().push(t);
+        };
+        let expected = params.len() + m.variadic as usize;
+        if params.is_empty() && !m.variadic && args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+        if m.variadic && args.len() == params.len() {
+            args.push(Vec::new());
+        }
+        if args.len() != expected {
+            panic!(
+                "macro {} requires {} arguments, but {} given",
+                m.name,
+                expected,
+                args.len()
+            );
+        }
+        (args, right_paren)
+    }
+    // Substitutes the arguments into the body of `m`, handling `#` and `##`, and adds
+    // `hideset` to every resulting token.
+    fn subst(
+        &mut self,
+        m: &Macro,
+        args: &[Vec<PPToken>],
+        hideset: &HashSet<Symbol>,
+    ) -> Vec<PPToken> {
+        let arg = |t: &PPToken| -> Option<&Vec<PPToken>> {
+            let name = t.name()?;
+            let params = m.params.as_ref()?;
+            if let Some(i) = params.iter().position(|p| *p == name) {
+                return Some(&args[i]);
+            }
+            if m.variadic && name.as_str() == "__VA_ARGS__" {
+                return args.last();
+            }
+            None
+        };
+        let body = &m.body;
+        let mut output: Vec<Replaced> = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            let t = &body[i];
+            if t.token == Token::Hash && m.params.is_some() {
+                let a = arg(&body[i + 1]).unwrap();
+                output.push(Replaced::Token(PPToken {
+                    token: stringize(a),
+                    bol: false,
+                    space: t.space,
+                    hideset: HashSet::new(),
+                    location: t.location.clone(),
+                }));
+                i += 2;
+                continue;
+            }
+            if t.token == Token::HashHash {
+                let rhs = &body[i + 1];
+                let operand = match arg(rhs) {
+                    Some(a) => a.clone(),
+                    None => vec![rhs.clone()],
+                };
+                // As in GNU cpp, `, ## __VA_ARGS__` drops the comma when there are no
+                // variable arguments, and pastes nothing when there are.
+                if m.variadic
+                    && rhs.token == Token::Identifier(Symbol::intern("__VA_ARGS__"))
+                    && matches!(output.last(), Some(Replaced::Token(t)) if t.token == Token::Comma)
+                {
+                    if operand.is_empty() {
+                        output.pop();
+                    }
+                    output.extend(operand.into_iter().map(Replaced::Token));
+                    i += 2;
+                    continue;
+                }
+                // The body neither starts nor ends with `##`, so its left operand is there.
+                let lhs = output.pop().unwrap();
+                let mut operand = operand.into_iter();
+                match (lhs, operand.next()) {
+                    (lhs, None) => output.push(lhs),
+                    (Replaced::Placemarker { space }, Some(first)) => {
+                        output.push(Replaced::Token(PPToken { space, ..first }));
+                    }
+                    (Replaced::Token(lhs), Some(first)) => {
+                        output.push(Replaced::Token(paste(&lhs, &first)));
+                    }
+                }
+                output.extend(operand.map(Replaced::Token));
+                i += 2;
+                continue;
+            }
+            if let Some(a) = arg(t) {
+                // The operand of `##` is not macro-expanded.
+                if body.get(i + 1).is_some_and(|t| t.token == Token::HashHash) {
+                    if a.is_empty() {
+                        output.push(Replaced::Placemarker { space: t.space });
+                    } else {
+                        output.extend(a.iter().cloned().map(Replaced::Token));
+                    }
+                    i += 1;
+                    continue;
+                }
+                let mut expanded = self.expand(a.clone(), false);
+                if let Some(first) = expanded.first_mut() {
+                    first.space = t.space;
+                }
+                output.extend(expanded.into_iter().map(Replaced::Token));
+                i += 1;
+                continue;
+            }
+            output.push(Replaced::Token(t.clone()));
+            i += 1;
+        }
+        output
+            .into_iter()
+            .filter_map(|r| match r {
+                Replaced::Token(mut t) => {
+                    t.hideset.extend(hideset.iter().copied());
+                    Some(t)
+                }
+                Replaced::Placemarker { .. } => None,
+            })
+            .collect()
+    }
+
+    fn directive(&mut self, tokens: &[PPToken], output: &mut Vec<PPToken>) {
+        let Some(directive) = tokens.first() else {
+            // The null directive.
+            return;
+        };
+        let rest = &tokens[1..];
+        match directive.name().map(Symbol::as_str) {
+            Some("define") => self.define_directive(rest),
+            Some("undef") => {
+                self.macros.remove(&macro_name("undef", rest));
+            }
+            Some("include") => self.include_directive(rest, output),
+            Some("pragma") => self.pragma_directive(rest, &directive.location),
+            Some("error") => panic!("{}: error: {}", directive.location, spell(rest)),
+            Some("warning") => eprintln!("{}: warning: {}", directive.location, spell(rest)),
+            _ => panic!("invalid preprocessing directive: {}", directive.token),
+        }
+    }
+    // Handles #if, #ifdef, #ifndef, #elif, #else and #endif, returning false for any other
+    // directive. Conditions are not evaluated inside a group that is being skipped.
+    fn conditional_directive(
+        &mut self,
+        tokens: &[PPToken],
+        conditionals: &mut Vec<Conditional>,
+    ) -> bool {
+        let Some(directive) = tokens.first().and_then(PPToken::name) else {
+            return false;
+        };
+        let rest = &tokens[1..];
+        let skipping = conditionals.iter().any(|c| !c.active);
+        match directive.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let active = !skipping
+                    && match directive.as_str() {
+                        "if" => self.evaluate_condition(rest),
+                        "ifdef" => self
+                            .macros
+                            .contains_key(&macro_name(directive.as_str(), rest)),
+                        _ => !self
+                            .macros
+                            .contains_key(&macro_name(directive.as_str(), rest)),
+                    };
+                conditionals.push(Conditional {
+                    active,
+                    taken: active || skipping,
+                    seen_else: false,
+                });
+            }
+            "elif" => {
+                let Some(c) = conditionals.last() else {
+                    panic!("#elif without #if");
+                };
+                if c.seen_else {
+                    panic!("#elif after #else");
+                }
+                let active = !c.taken && self.evaluate_condition(rest);
+                let c = conditionals.last_mut().unwrap();
+                c.active = active;
+                c.taken |= active;
+            }
+            "else" => {
+                let Some(c) = conditionals.last_mut() else {
+                    panic!("#else without #if");
+                };
+                if c.seen_else {
+                    panic!("#else after #else");
+                }
+                c.active = !c.taken;
+                c.taken = true;
+                c.seen_else = true;
+            }
+            "endif" => {
+                if conditionals.pop().is_none() {
+                    panic!("#endif without #if");
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+    // Evaluates the controlling expression of #if or #elif. `defined` operators are
+    // resolved before macro expansion, and identifiers left after it evaluate to 0.
+    fn evaluate_condition(&mut self, tokens: &[PPToken]) -> bool {
+        let mut resolved = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            if tokens[i].name().map(Symbol::as_str) == Some("__has_include") {
+                let end = tokens[i..]
+                    .iter()
+                    .position(|t| t.token == Token::RightParen)
+                    .map(|p| i + p);
+                let found = match (tokens.get(i + 1), end) {
+                    (Some(l), Some(end)) if l.token == Token::LeftParen => {
+                        match include_name(&tokens[i + 2..end]) {
+                            Some((name, quoted)) => self.find_include(&name, quoted).is_some(),
+                            None => panic!("__has_include expects \"FILENAME\" or <FILENAME>"),
+                        }
+                    }
+                    _ => panic!("missing '(' after __has_include"),
+                };
+                resolved.push(PPToken {
+                    token: Token::number(found as i64),
+                    ..tokens[i].clone()
+                });
+                i = end.unwrap() + 1;
+                continue;
+            }
+            if tokens[i].name().map(Symbol::as_str) != Some("defined") {
+                resolved.push(tokens[i].clone());
+                i += 1;
+                continue;
+            }
+            let (name, next) = match (tokens.get(i + 1), tokens.get(i + 2), tokens.get(i + 3)) {
+                (Some(l), Some(name), Some(r))
+                    if l.token == Token::LeftParen && r.token == Token::RightParen =>
+                {
+                    (name.name(), i + 4)
+                }
+                (Some(name), ..) => (name.name(), i + 2),
+                _ => (None, i + 1),
+            };
+            let Some(name) = name else {
+                panic!("operator \"defined\" requires an identifier");
+            };
+            resolved.push(PPToken {
+                token: Token::number(self.macros.contains_key(&name) as i64),
+                ..tokens[i].clone()
+            });
+            i = next;
+        }
+        let mut expression = self
+            .expand(resolved, false)
+            .into_iter()
+            .map(|t| match t.token {
+                Token::Identifier(_) | Token::Keyword(_) => Token::number(0),
+                token => token,
+            })
+            .collect::<Vec<Token>>();
+        if expression.is_empty() {
+            panic!("#if with no expression");
+        }
+        expression.push(Token::EOF);
+        let mut parser = Parser::new(expression);
+        let node = parser.constant_expression();
+        match constant::evaluate(parser.ast(), node) {
+            Ok(v) => v.value != 0,
+            Err(e) => panic!("{} in #if", e),
+        }
+    }
+    // Applies `#line number ["file"]` given at `hash` to the tokens in `rest`, which all
+    // come from the same file.
+    fn line_directive(&mut self, hash: &PPToken, tokens: &[PPToken], rest: &mut [PPToken]) {
+        let tokens = match tokens.first() {
+            Some(PPToken {
+                token: Token::Number(..),
+                ..
+            }) => tokens.to_vec(),
+            _ => self.expand(tokens.to_vec(), false),
+        };
+        let (line, file) = match tokens.iter().map(|t| &t.token).collect::<Vec<&Token>>()[..] {
+            [Token::Number(line)] => (line.as_str(), None),
+            [Token::Number(line), Token::String(file), ..] => {
+                match convert_string_literal(file.as_str()) {
+                    Ok(file) => (
+                        line.as_str(),
+                        Some(Rc::from(String::from_utf8_lossy(&file).as_ref())),
+                    ),
+                    Err(message) => panic!("invalid filename in #line directive: {}", message),
+                }
+            }
+            _ => panic!("#line directive requires a simple digit sequence"),
+        };
+        if !line.bytes().all(|c| c.is_ascii_digit()) {
+            panic!("#line directive requires a simple digit sequence");
+        }
+        let Ok(line) = line.parse::<usize>() else {
+            panic!("line number out of range in #line directive");
+        };
+        // The line after the directive becomes `line`.
+        let next = hash.location.line + 1;
+        for t in rest.iter_mut() {
+            if t.location.file != hash.location.file || t.location.line < next {
+                continue;
+            }
+            t.location.line = t.location.line + line - next;
+            if let Some(file) = &file {
+                t.location.file = Rc::clone(file);
+            }
+        }
+    }
+    fn include_directive(&mut self, tokens: &[PPToken], output: &mut Vec<PPToken>) {
+        let (name, quoted) = match include_name(tokens) {
+            Some(v) => v,
+            // `#include MACRO`: the line is macro-expanded and must then give a file name.
+            None => match include_name(&self.expand(tokens.to_vec(), false)) {
+                Some(v) => v,
+                None => panic!("#include expects \"FILENAME\" or <FILENAME>"),
+            },
+        };
+        match self.find_include(&name, quoted) {
+            Some(path) => self.include_file(path, output),
+            None => panic!("{}: No such file or directory", name),
+        }
+    }
+    fn find_include(&self, name: &str, quoted: bool) -> Option<PathBuf> {
+        if Path::new(name).is_absolute() {
+            return Some(PathBuf::from(name)).filter(|p| p.is_file());
+        }
+        let current_dir = match self.include_stack.last() {
+            Some(file) => file.parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+        quoted
+            .then_some(&current_dir)
+            .into_iter()
+            .chain(&self.include_paths)
+            .cloned()
+            .chain(SYSTEM_INCLUDE_PATHS.iter().map(PathBuf::from))
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file())
+    }
+    fn include_file(&mut self, path: PathBuf, output: &mut Vec<PPToken>) {
+        let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if self.pragma_once.contains(&key) {
+            return;
+        }
+        if let Some(guard) = self.include_guards.get(&key) {
+            if self.macros.contains_key(guard) {
+                return;
+            }
+        }
+        if self.include_stack.len() >= MAX_INCLUDE_DEPTH {
+            panic!(
+                "#include nested depth {} exceeds maximum of {}",
+                self.include_stack.len(),
+                MAX_INCLUDE_DEPTH
+            );
+        }
+        let mut source = match fs::read_to_string(&path) {
+            Ok(v) => v,
+            Err(e) => panic!("{}: {}", path.display(), e),
+        };
+        if self.trigraphs {
+            source = replace_trigraphs(&source);
+        }
+        let file: Rc<str> = Rc::from(path.display().to_string());
+        let tokens = to_pp_tokens(Lexer::with_file(&source, file.clone()), &file);
+        if let Some(guard) = include_guard(&tokens) {
+            self.include_guards.insert(key, guard);
+        }
+        self.include_stack.push(path);
+        let tokens = self.expand(tokens, true);
+        self.include_stack.pop();
+        output.extend(tokens);
+    }
+    fn pragma_directive(&mut self, tokens: &[PPToken], location: &Location) {
+        let kind = match tokens.first().and_then(PPToken::name).map(Symbol::as_str) {
+            Some("once") if tokens.len() == 1 => {
+                if let Some(file) = self.include_stack.last() {
+                    let key = fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+                    self.pragma_once.insert(key);
+                }
+                Some(PragmaKind::Once)
+            }
+            Some("pack") => match pack_pragma(&tokens[1..]) {
+                Ok(pack) => Some(PragmaKind::Pack(pack)),
+                Err(message) => {
+                    eprintln!("{}: warning: {}, #pragma pack ignored", location, message);
+                    return;
+                }
+            },
+            _ => None,
+        };
+        match kind {
+            Some(kind) => self.pragmas.push(Pragma {
+                kind,
+                location: location.clone(),
+            }),
+            None if self.warn_unknown_pragmas => {
+                eprintln!("{}: warning: ignoring #pragma {}", location, spell(tokens));
+            }
+            None => {}
+        }
+    }
+    // `_Pragma("...")` acts like a #pragma line holding the contents of the string.
+    fn pragma_operator(&mut self, t: &PPToken, rest: &mut Vec<PPToken>) {
+        let mut operand = Vec::new();
+        for _ in 0..3 {
+            operand.extend(rest.pop().map(|t| t.token));
+        }
+        let [Token::LeftParen, Token::String(v), Token::RightParen] = &operand[..] else {
+            panic!(
+                "{}: _Pragma takes a parenthesized string literal",
+                t.location
+            );
+        };
+        // The string is destringized: the quotes are dropped and `\"` and `\\` unescaped.
+        let mut source = String::new();
+        let mut chars = v.as_str()[1..v.as_str().len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => source.extend(chars.next()),
+                c => source.push(c),
+            }
+        }
+        let tokens = to_pp_tokens(Lexer::new(&source), &t.location.file)
+            .into_iter()
+            .map(|pt| PPToken {
+                location: t.location.clone(),
+                ..pt
+            })
+            .collect::<Vec<PPToken>>();
+        self.pragma_directive(&tokens, &t.location);
+    }
+    fn define_directive(&mut self, tokens: &[PPToken]) {
+        let Some(name) = tokens.first().and_then(PPToken::name) else {
+            panic!("macro name must be an identifier");
+        };
+        let mut rest = &tokens[1..];
+        let mut params = None;
+        let mut variadic = false;
+        // Only a `(` directly after the name starts a parameter list.
+        if let Some(PPToken {
+            token: Token::LeftParen,
+            space: false,
+            ..
+        }) = rest.first()
+        {
+            let (p, v, body) = read_parameters(name.as_str(), &rest[1..]);
+            params = Some(p);
+            variadic = v;
+            rest = body;
+        }
+        let mut body = rest.to_vec();
+        if let Some(first) = body.first_mut() {
+            first.space = false;
+        }
+        if body.first().is_some_and(|t| t.token == Token::HashHash)
+            || body.last().is_some_and(|t| t.token == Token::HashHash)
+        {
+            panic!("'##' cannot appear at either end of a macro expansion");
+        }
+        if let Some(p) = &params {
+            for (i, t) in body.iter().enumerate() {
+                if t.token != Token::Hash {
+                    continue;
+                }
+                let is_param = match body.get(i + 1).and_then(PPToken::name) {
+                    Some(v) => p.contains(&v) || (variadic && v.as_str() == "__VA_ARGS__"),
+                    None => false,
+                };
+                if !is_param {
+                    panic!("'#' is not followed by a macro parameter");
+                }
+            }
+        }
+        self.define_macro(Macro {
+            name,
+            params,
+            variadic,
+            body,
+            builtin: None,
+        });
+    }
+    fn define_macro(&mut self, m: Macro) {
+        if let Some(old) = self.macros.get(&m.name) {
+            if *old != m {
+                eprintln!("warning: \"{}\" redefined", m.name);
+            }
+        }
+        self.macros.insert(m.name, m);
+    }
+}
+
+// Formats seconds since the Unix epoch (in UTC) as the values of __DATE__ and __TIME__,
+// e.g. "Oct  8 2026" and "09:05:00".
+fn format_date_time(secs: u64) -> (String, String) {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    // Howard Hinnant's days-to-civil algorithm.
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let date = format!("{} {:2} {}", MONTHS[month as usize - 1], day, year);
+    let secs = secs % 86400;
+    let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    (date, time)
+}
+
+// Returns the macro name operand of #ifdef, #ifndef or #undef.
+fn macro_name(directive: &str, tokens: &[PPToken]) -> Symbol {
+    match tokens {
+        [t] if t.name().is_some() => t.name().unwrap(),
+        _ => panic!("#{} expects a single macro name", directive),
+    }
+}
+
+// Finds the macro guarding a whole file, i.e. the X of a file that consists of nothing
+// but `#ifndef X ... #endif`.
+fn include_guard(tokens: &[PPToken]) -> Option<Symbol> {
+    let directive = |i: usize| -> Option<&'static str> {
+        if tokens[i].token == Token::Hash && tokens[i].bol {
+            tokens
+                .get(i + 1)
+                .filter(|t| !t.bol)
+                .and_then(PPToken::name)
+                .map(Symbol::as_str)
+        } else {
+            None
+        }
+    };
+    if tokens.is_empty() || directive(0) != Some("ifndef") {
+        return None;
+    }
+    let guard = match tokens.get(2) {
+        Some(t) if !t.bol && tokens.get(3).is_none_or(|t| t.bol) => t.name()?,
+        _ => return None,
+    };
+    let mut depth = 0;
+    for i in 0..tokens.len() {
+        match directive(i) {
+            Some("if" | "ifdef" | "ifndef") => depth += 1,
+            Some("elif" | "else") if depth == 1 => return None,
+            Some("endif") => {
+                depth -= 1;
+                if depth == 0 {
+                    // Only a file that ends with this #endif line is guarded.
+                    return tokens[i + 1..].iter().all(|t| !t.bol).then_some(guard);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Returns the file name of an include directive and whether it was given in quotes.
+fn include_name(tokens: &[PPToken]) -> Option<(String, bool)> {
+    match tokens {
+        // Escape sequences mean nothing in a file name, so the text is taken as it is.
+        [PPToken {
+            token: Token::String(v),
+            ..
+        }] => Some((v.as_str()[1..v.as_str().len() - 1].to_string(), true)),
+        [PPToken {
+            token: Token::LessThan,
+            ..
+        }, rest @ .., PPToken {
+            token: Token::GreaterThan,
+            ..
+        }] => Some((spell(rest), false)),
+        _ => None,
+    }
+}
+
+// Reads `a, b, ...)` and returns the parameter names, whether the macro is variadic, and
+// the tokens after the closing parenthesis.
+fn read_parameters<'a>(name: &str, tokens: &'a [PPToken]) -> (Vec<Symbol>, bool, &'a [PPToken]) {
+    let mut params = Vec::new();
+    let mut i = 0;
+    if tokens.first().map(|t| &t.token) == Some(&Token::RightParen) {
+        return (params, false, &tokens[1..]);
+    }
+    loop {
+        match tokens.get(i).map(|t| &t.token) {
+            Some(Token::Ellipsis) => {
+                if tokens.get(i + 1).map(|t| &t.token) != Some(&Token::RightParen) {
+                    panic!("missing ')' in parameter list of macro: {}", name);
+                }
+                return (params, true, &tokens[i + 2..]);
+            }
+            Some(_) if tokens[i].name().is_some() => {
+                let p = tokens[i].name().unwrap();
+                if params.contains(&p) || p.as_str() == "__VA_ARGS__" {
+                    panic!("duplicate macro parameter \"{}\" in macro: {}", p, name);
+                }
+                params.push(p);
+            }
+            _ => panic!("invalid parameter list of macro: {}", name),
+        }
+        match tokens.get(i + 1).map(|t| &t.token) {
+            Some(Token::Comma) => i += 2,
+            Some(Token::RightParen) => return (params, false, &tokens[i + 2..]),
+            _ => panic!("missing ')' in parameter list of macro: {}", name),
+        }
+    }
+}
+
+// Folds the Newline and Space tokens produced by the lexer into flags on the next token.
+// This collects a whole file: the lexer streams, but the preprocessor does not, since
+// include guard detection and #line look at the rest of the file.
+// A line break counts as whitespace too, which matters where it ends up inside a line,
+// as in a macro argument that spans lines.
+fn to_pp_tokens(tokens: impl IntoIterator<Item = Token>, file: &Rc<str>) -> Vec<PPToken> {
+    let mut output = Vec::new();
+    let mut bol = true;
+    let mut space = false;
+    let mut line = 1;
+    for token in tokens {
+        match token {
+            Token::Newline => {
+                bol = true;
+                space = true;
+                line += 1;
+            }
+            Token::Space => space = true,
+            Token::EOF => {}
+            token => {
+                output.push(PPToken {
+                    token,
+                    bol,
+                    space,
+                    hideset: HashSet::new(),
+                    location: Location {
+                        file: Rc::clone(file),
+                        line,
+                    },
+                });
+                bol = false;
+                space = false;
+            }
+        }
+    }
+    output
+}
+
+// The `#` operator: spells the argument as a string literal, with a single space wherever
+// the argument had whitespace between two tokens. Only the `\` and `"` inside string
+// literals and character constants are escaped; other tokens are copied as they are.
+fn stringize(arg: &[PPToken]) -> Token {
+    let mut s = String::from("\"");
+    for (i, t) in arg.iter().enumerate() {
+        if i > 0 && t.space {
+            s.push(' ');
+        }
+        match t.token {
+            Token::String(v) | Token::Character(v) => {
+                for c in v.as_str().chars() {
+                    if c == '\\' || c == '"' {
+                        s.push('\\');
+                    }
+                    s.push(c);
+                }
+            }
+            ref token => s.push_str(&token.to_string()),
+        }
+    }
+    s.push('"');
+    Token::String(Symbol::intern(&s))
+}
+
+// Spells tokens as source text, with a single space wherever there was whitespace.
+fn spell(tokens: &[PPToken]) -> String {
+    let mut s = String::new();
+    for (i, t) in tokens.iter().enumerate() {
+        if i > 0 && t.space {
+            s.push(' ');
+        }
+        s.push_str(&t.token.to_string());
+    }
+    s
+}
+
+// Whether `lhs` and `rhs` written without a space between them would lex differently, as
+// `+` and `+` make `++`, or `/` and `*` start a comment.
+fn needs_separator(lhs: &Token, rhs: &Token) -> bool {
+    let s = format!("{}{}", lhs, rhs);
+    if s.contains("//") || s.contains("/*") {
+        return true;
+    }
+    let tokens = Lexer::new(&s)
+        .filter(|t| *t != Token::EOF)
+        .collect::<Vec<Token>>();
+    tokens != [lhs.clone(), rhs.clone()]
+}
+
+// Parses the operand of `#pragma pack`.
+fn pack_pragma(tokens: &[PPToken]) -> Result<Pack, String> {
+    let alignment = |t: &Token| match t {
+        Token::Number(v) => match convert_integer_literal(v.as_str()) {
+            Ok((v @ (1 | 2 | 4 | 8 | 16), _)) => Ok(v as u64),
+            _ => Err(format!("alignment must be a small power of two, not {}", t)),
+        },
+        _ => Err(format!("alignment must be a small power of two, not {}", t)),
+    };
+    let operand = tokens.iter().map(|t| &t.token).collect::<Vec<&Token>>();
+    let [Token::LeftParen, args @ .., Token::RightParen] = &operand[..] else {
+        return Err("missing parenthesis after '#pragma pack'".to_string());
+    };
+    let name = |t: &Token| match t {
+        Token::Identifier(v) => v.as_str(),
+        _ => "",
+    };
+    match args {
+        [] => Ok(Pack::Set(None)),
+        [n @ Token::Number(..)] => Ok(Pack::Set(Some(alignment(n)?))),
+        [t] if name(t) == "pop" => Ok(Pack::Pop),
+        [t] if name(t) == "push" => Ok(Pack::Push(None)),
+        [t, Token::Comma, n] if name(t) == "push" => Ok(Pack::Push(Some(alignment(n)?))),
+        _ => Err("malformed '#pragma pack'".to_string()),
+    }
+}
+
+// A token of a macro body after its parameters are replaced, while `##` is applied. An
+// empty argument next to `##` becomes a placemarker, so that pasting onto it gives the
+// other operand unchanged, and pasting two of them gives another (C11 6.10.3.3).
+enum Replaced {
+    Token(PPToken),
+    Placemarker { space: bool },
+}
+
+// The `##` operator: joins the spellings of both tokens and lexes the result again.
+fn paste(lhs: &PPToken, rhs: &PPToken) -> PPToken {
+    let s = format!("{}{}", lhs.token, rhs.token);
+    let tokens = to_pp_tokens(Lexer::new(&s), &lhs.location.file);
+    let [t] = tokens.as_slice() else {
+        panic!(
+            "pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+            lhs.token, rhs.token
+        );
+    };
+    PPToken {
+        token: t.token.clone(),
+        bol: false,
+        space: lhs.space,
+        hideset: lhs.hideset.clone(),
+        location: lhs.location.clone(),
+    }
+}
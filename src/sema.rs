@@ -0,0 +1,348 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    constant,
+    parser::{
+        walk_binary_operation, walk_conditional, walk_unary_operation, Ast, BinaryOperationAST,
+        BinaryOperator, ConditionalAST, DeclarationAST, GlobalVariable, GlobalVariableAST,
+        LocalVariableAST, NodeId, NumberLiteralAST, SideTable, StorageClass, StringLiteralAST,
+        Type, TypeKind, UnaryOperationAST, UnaryOperator, Variable, Visitor, AST,
+    },
+    symbol::Symbol,
+};
+
+// Checks a parsed program and works out what it means: which variable each identifier
+// refers to, where locals live on the stack and the type of each expression. The
+// results go to the side tables of the `Ast`, so the tree stays as the parser built it.
+pub struct Sema {
+    local_variable_map: HashMap<Symbol, LocalVariableAST>,
+    local_variable_current_offset: i64,
+    global_variable_map: HashMap<Symbol, GlobalVariableAST>,
+    global_variables: Vec<GlobalVariable>,
+    // The initializers of `global_variables` by index, which are evaluated once the types
+    // of the whole program are known, since `sizeof` needs them.
+    static_initializers: Vec<(usize, NodeId)>,
+    // The type of each variable in scope, qualifiers included.
+    variable_types: HashMap<Symbol, Type>,
+    symbols: SideTable<Variable>,
+    types: SideTable<Type>,
+}
+
+impl Default for Sema {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sema {
+    pub fn new() -> Self {
+        Sema {
+            local_variable_map: HashMap::new(),
+            local_variable_current_offset: 8,
+            global_variable_map: HashMap::new(),
+            global_variables: Vec::new(),
+            static_initializers: Vec::new(),
+            variable_types: HashMap::new(),
+            symbols: SideTable::default(),
+            types: SideTable::default(),
+        }
+    }
+    // Analyzes the statements of a whole program, in order, and fills `ast.symbols`
+    // and `ast.types`.
+    pub fn analyze(&mut self, ast: &mut Ast, nodes: &[NodeId]) {
+        for &node in nodes {
+            self.visit(ast, node);
+        }
+        ast.symbols = mem::take(&mut self.symbols);
+        ast.types = mem::take(&mut self.types);
+        for (i, init) in mem::take(&mut self.static_initializers) {
+            match constant::evaluate(ast, init) {
+                Ok(v) => self.global_variables[i].init = Some(v.value),
+                Err(e) => error(ast, e.node, &format!("{} in static initializer", e)),
+            }
+        }
+    }
+    // The number of stack slots handed out to locals, which the frame must cover.
+    pub fn local_variable_count(&self) -> usize {
+        (self.local_variable_current_offset as usize - 8) / 8
+    }
+    pub fn global_variables(&self) -> &[GlobalVariable] {
+        &self.global_variables
+    }
+    fn declare_local_variable(&mut self, name: Symbol, type_: Type) -> LocalVariableAST {
+        let variable = LocalVariableAST {
+            name,
+            offset: self.local_variable_current_offset,
+            qualifiers: type_.qualifiers,
+        };
+        self.local_variable_map.insert(name, variable);
+        self.variable_types.insert(name, type_);
+        self.local_variable_current_offset += 8;
+        variable
+    }
+    fn is_declared(&self, name: Symbol) -> bool {
+        self.variable_types.contains_key(&name)
+    }
+    fn type_of(&self, id: NodeId) -> Type {
+        self.types
+            .get(id)
+            .cloned()
+            .unwrap_or(Type::new(TypeKind::Long))
+    }
+    // Checks that `lhs` may be the target of an assignment: it must designate an object
+    // whose type is not const.
+    fn check_assignable(&self, ast: &Ast, lhs: NodeId) {
+        // A string literal is an lvalue, but not a modifiable one.
+        if !is_lvalue(ast, lhs) || matches!(ast[lhs], AST::StringLiteral(_)) {
+            error(ast, lhs, "expression is not assignable");
+        }
+        if self.type_of(lhs).qualifiers.const_ {
+            match self.symbols.get(lhs) {
+                Some(variable) => error(
+                    ast,
+                    lhs,
+                    &format!("cannot assign to const variable: {}", variable.name()),
+                ),
+                None => error(ast, lhs, "cannot assign to a const-qualified object"),
+            }
+        }
+    }
+}
+
+impl Visitor for Sema {
+    fn visit_unary_operation(&mut self, ast: &Ast, id: NodeId, node: &UnaryOperationAST) {
+        walk_unary_operation(self, ast, node);
+        match node.op {
+            UnaryOperator::AddrOf if !is_lvalue(ast, node.operand) => {
+                error(ast, node.operand, "cannot take the address of an rvalue");
+            }
+            UnaryOperator::PreIncrement
+            | UnaryOperator::PreDecrement
+            | UnaryOperator::PostIncrement
+            | UnaryOperator::PostDecrement => self.check_assignable(ast, node.operand),
+            _ => {}
+        }
+        let type_ = match node.op {
+            // Without array types, the address of a string literal is the address of its
+            // first character, which is what the literal itself evaluates to.
+            UnaryOperator::AddrOf => match ast[node.operand] {
+                AST::StringLiteral(_) => self.type_of(node.operand),
+                _ => Type::pointer_to(self.type_of(node.operand)),
+            },
+            UnaryOperator::Deref => match self.type_of(node.operand).pointee() {
+                Some(pointee) => pointee.clone(),
+                None => Type::new(TypeKind::Long),
+            },
+            UnaryOperator::Neg | UnaryOperator::Plus | UnaryOperator::BitNot => {
+                arithmetic_type(&self.type_of(node.operand), &Type::new(TypeKind::Long))
+            }
+            UnaryOperator::Not => Type::new(TypeKind::Long),
+            UnaryOperator::PreIncrement
+            | UnaryOperator::PreDecrement
+            | UnaryOperator::PostIncrement
+            | UnaryOperator::PostDecrement => self.type_of(node.operand).unqualified(),
+            // The type of `sizeof` is size_t.
+            UnaryOperator::Sizeof => Type::new(TypeKind::UnsignedLong),
+        };
+        self.types.insert(id, type_);
+    }
+    fn visit_binary_operation(&mut self, ast: &Ast, id: NodeId, node: &BinaryOperationAST) {
+        match ast[node.lhs] {
+            // A variable that is first used as the target of `=` gets the type of the
+            // value, so that after `p = &k` the pointer `p` keeps the qualifiers of `k`.
+            AST::Identifier(name)
+                if node.op == BinaryOperator::Assign && !self.is_declared(name) =>
+            {
+                self.visit(ast, node.rhs);
+                // The right side may have used, and so declared, the variable already.
+                if !self.is_declared(name) {
+                    self.declare_local_variable(name, self.type_of(node.rhs).unqualified());
+                }
+                self.visit(ast, node.lhs);
+            }
+            _ => walk_binary_operation(self, ast, node),
+        }
+        if node.op == BinaryOperator::Assign {
+            self.check_assignable(ast, node.lhs);
+        }
+        // Only lvalues keep their qualifiers; every operator gives a plain value.
+        let lhs = self.type_of(node.lhs).unqualified();
+        let rhs = self.type_of(node.rhs).unqualified();
+        let type_ = match node.op {
+            BinaryOperator::Add => match (lhs.pointee(), rhs.pointee()) {
+                (Some(_), _) => lhs,
+                (_, Some(_)) => rhs,
+                _ => arithmetic_type(&lhs, &rhs),
+            },
+            BinaryOperator::Sub => match (lhs.pointee(), rhs.pointee()) {
+                (Some(_), None) => lhs,
+                (Some(_), Some(_)) => Type::new(TypeKind::Long),
+                _ => arithmetic_type(&lhs, &rhs),
+            },
+            BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Remainder
+            | BinaryOperator::BitAnd
+            | BinaryOperator::BitXor
+            | BinaryOperator::BitOr => arithmetic_type(&lhs, &rhs),
+            // A shift has the type of its left operand.
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => {
+                arithmetic_type(&lhs, &Type::new(TypeKind::Long))
+            }
+            BinaryOperator::Assign => lhs,
+            BinaryOperator::Comma => rhs,
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqual
+            | BinaryOperator::LogicalAnd
+            | BinaryOperator::LogicalOr => Type::new(TypeKind::Long),
+        };
+        self.types.insert(id, type_);
+    }
+    fn visit_compound_assignment(&mut self, ast: &Ast, id: NodeId, node: &BinaryOperationAST) {
+        walk_binary_operation(self, ast, node);
+        self.check_assignable(ast, node.lhs);
+        self.types.insert(id, self.type_of(node.lhs).unqualified());
+    }
+    fn visit_number_literal(&mut self, _ast: &Ast, id: NodeId, node: &NumberLiteralAST) {
+        let kind = if node.type_.is_unsigned() {
+            TypeKind::UnsignedLong
+        } else {
+            TypeKind::Long
+        };
+        self.types.insert(id, Type::new(kind));
+    }
+    fn visit_string_literal(&mut self, _ast: &Ast, id: NodeId, _node: &StringLiteralAST) {
+        self.types
+            .insert(id, Type::pointer_to(Type::new(TypeKind::Char)));
+    }
+    fn visit_identifier(&mut self, _ast: &Ast, id: NodeId, name: Symbol) {
+        let variable = if let Some(&variable) = self.global_variable_map.get(&name) {
+            Variable::Global(variable)
+        } else if let Some(&variable) = self.local_variable_map.get(&name) {
+            Variable::Local(variable)
+        } else {
+            // Variables that are used without a declaration are locals.
+            Variable::Local(self.declare_local_variable(name, Type::new(TypeKind::Long)))
+        };
+        self.symbols.insert(id, variable);
+        self.types.insert(id, self.variable_types[&name].clone());
+    }
+    fn visit_conditional(&mut self, ast: &Ast, id: NodeId, node: &ConditionalAST) {
+        walk_conditional(self, ast, node);
+        let then = self.type_of(node.then).unqualified();
+        let type_ = match then.pointee() {
+            Some(_) => then,
+            None => arithmetic_type(&then, &self.type_of(node.else_)),
+        };
+        self.types.insert(id, type_);
+    }
+    fn visit_declaration(&mut self, ast: &Ast, id: NodeId, node: &DeclarationAST) {
+        let name = node.name;
+        let qualifiers = node.specifiers.qualifiers;
+        if let Some(&previous) = self.global_variable_map.get(&name) {
+            // An extern declaration may be repeated, as long as it declares the same object.
+            if previous.label == name
+                && node.specifiers.storage_class == Some(StorageClass::Extern)
+                && node.init.is_none()
+            {
+                if previous.qualifiers != qualifiers {
+                    error(ast, id, &format!("conflicting qualifiers for: {}", name));
+                }
+                self.symbols.insert(id, Variable::Global(previous));
+                return;
+            }
+        }
+        if self.local_variable_map.contains_key(&name)
+            || self.global_variable_map.contains_key(&name)
+        {
+            error(ast, id, &format!("redeclaration of variable: {}", name));
+        }
+        let type_ = Type {
+            kind: TypeKind::Long,
+            qualifiers,
+        };
+        let variable = match node.specifiers.storage_class {
+            Some(StorageClass::Static) => {
+                // Statics live in the data section under a uniquified label, so that
+                // they keep their value across calls and never clash with other symbols.
+                let label = Symbol::intern(&format!("{}.{}", name, self.global_variables.len()));
+                let variable = GlobalVariableAST {
+                    name,
+                    label,
+                    qualifiers,
+                };
+                self.global_variable_map.insert(name, variable);
+                self.variable_types.insert(name, type_);
+                if let Some(init) = node.init {
+                    self.visit(ast, init);
+                    self.static_initializers
+                        .push((self.global_variables.len(), init));
+                }
+                self.global_variables.push(GlobalVariable {
+                    label,
+                    // Filled in by `analyze`.
+                    init: None,
+                    qualifiers,
+                });
+                Variable::Global(variable)
+            }
+            Some(StorageClass::Extern) => {
+                if node.init.is_some() {
+                    error(
+                        ast,
+                        id,
+                        &format!("extern variable has an initializer: {}", name),
+                    );
+                }
+                let variable = GlobalVariableAST {
+                    name,
+                    label: name,
+                    qualifiers,
+                };
+                self.global_variable_map.insert(name, variable);
+                self.variable_types.insert(name, type_);
+                Variable::Global(variable)
+            }
+            None => {
+                let variable = Variable::Local(self.declare_local_variable(name, type_));
+                if let Some(init) = node.init {
+                    self.visit(ast, init);
+                }
+                variable
+            }
+        };
+        self.symbols.insert(id, variable);
+    }
+}
+
+// The type of an arithmetic operation on integers: unsigned if either operand is, as the
+// constant evaluator does it.
+fn arithmetic_type(lhs: &Type, rhs: &Type) -> Type {
+    if lhs.is_unsigned() || rhs.is_unsigned() {
+        Type::new(TypeKind::UnsignedLong)
+    } else {
+        Type::new(TypeKind::Long)
+    }
+}
+
+// Whether an expression designates an object, whose address can be taken and which
+// can be assigned to unless it is a string literal.
+fn is_lvalue(ast: &Ast, id: NodeId) -> bool {
+    match &ast[id] {
+        AST::Identifier(_) | AST::StringLiteral(_) => true,
+        AST::UnaryOperation(node) => node.op == UnaryOperator::Deref,
+        _ => false,
+    }
+}
+
+// Reports an error at a node, prefixed with its location when known.
+fn error(ast: &Ast, id: NodeId, message: &str) -> ! {
+    match ast.spans.get(id) {
+        Some(location) => panic!("{}: {}", location, message),
+        None => panic!("{}", message),
+    }
+}
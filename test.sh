@@ -3,12 +3,29 @@
 set -e
 
 function cleanup() {
-	rm -f tmp tmp.s tmp.out
+	rm -f tmp tmp.s tmp.out tmp.err tmp2.o tmp_main.c
+	rm -rf tmp_inc
 }
 
+# definitions referenced from the tests through `extern`
+echo 'long ext_value = 42;' | cc -xc -c -o tmp2.o -
+
+# headers for the #include tests
+mkdir -p tmp_inc/sys
+echo '#define FROM_A 3' >tmp_inc/a.h
+echo '#define FROM_B 5' >tmp_inc/b.h
+printf '#include "b.h"\n#define FROM_NESTED (FROM_B + 1)\n' >tmp_inc/nested.h
+echo '#define SYS 7' >tmp_inc/sys/s.h
+printf '#pragma once\ncount = count + 1;\n' >tmp_inc/once.h
+echo 'count = count + 1;' >tmp_inc/twice.h
+echo '#include "self.h"' >tmp_inc/self.h
+printf '#include "tmp_inc/b.h"\nreturn FROM_B;\n' >tmp_main.c
+printf '#ifndef GUARD_H\n#define GUARD_H\ncount = count + 1;\n#endif\n' >tmp_inc/guard.h
+printf 'a = 1;\n/* unterminated\n' >tmp_inc/comment.h
+
 function expect() {
-	cargo run -- "${2}" >tmp.s 2>/dev/null
-	cc -o tmp tmp.s
+	cargo run -- "${@:3}" "${2}" >tmp.s 2>/dev/null
+	cc -o tmp tmp.s tmp2.o
 	set +e
 	(
 		./tmp
@@ -27,6 +44,61 @@ function expect() {
 	fi
 }
 
+function expect_error() {
+	if cargo run -- "${@:2}" "${1}" >/dev/null 2>&1; then
+		echo "${1} => error ng"
+		echo "expected a compile error"
+		echo "NG!"
+		cleanup
+		exit 1
+	else
+		echo "${1} => error ok"
+	fi
+}
+
+function expect_error_message() {
+	if cargo run -- "${@:3}" "${2}" >/dev/null 2>tmp.err; then
+		echo "${2} => error ng"
+		echo "expected a compile error"
+		echo "NG!"
+		cleanup
+		exit 1
+	fi
+	check_stderr "${@}"
+}
+
+function expect_warning() {
+	cargo run -- "${@:3}" "${2}" >/dev/null 2>tmp.err
+	check_stderr "${@}"
+}
+
+function check_stderr() {
+	if grep -qF -- "${1}" tmp.err; then
+		echo "${2} => ${1} ok"
+	else
+		echo "${2} => ${1} ng"
+		echo "expected: ${1}"
+		echo "but got: $(cat tmp.err)"
+		echo "NG!"
+		cleanup
+		exit 1
+	fi
+}
+
+function expect_preprocessed() {
+	actual="$(cargo run -- -E "${@:3}" "${2}" 2>/dev/null)"
+	if [ "${actual}" = "${1}" ]; then
+		echo "${2} => ${1} ok"
+	else
+		echo "${2} => ${1} ng"
+		echo "expected: ${1}"
+		echo "but got: ${actual}"
+		echo "NG!"
+		cleanup
+		exit 1
+	fi
+}
+
 expect "0" "return 0;"
 expect "1" "return 1;"
 expect "2" "1+1;"
@@ -71,6 +143,684 @@ expect "60" "sum=0; for (i=10; i<15; i=i+1) sum=sum+i; sum;"
 
 expect "89" "i=1; j=1; for (k=0; k<10; k=k+1) { m=i+j; i=j; j=m; } return i;"
 
+expect "0" "static x; return x;"
+expect "4" "static x = 3; x = x + 1; return x;"
+expect "5" "static x = -2; static y = 7; return x + y;"
+expect "8" "a = 1; static b = 2; c = 3; b = b + 2; return a + b + c;"
+expect "42" "extern ext_value; return ext_value;"
+expect "43" "extern ext_value; ext_value = ext_value + 1; return ext_value;"
+
+expect "6" "const c = 3; return c * 2;"
+expect "5" "static const x = 5; return x;"
+expect "9" "const static x = 9; return x;"
+expect "4" "volatile v = 3; v = v + 1; return v;"
+expect "7" "i = 2; const volatile c = i + 5; return c;"
+expect "42" "extern const ext_value; return ext_value;"
+expect_error "const c = 1; c = 2;"
+expect_error "static const x = 1; x = 2;"
+expect_error "extern const ext_value; ext_value = 1;"
+expect_error "static extern x;"
+expect_error "a = 1; const a = 2;"
+expect "3" "a = 1; volatile b = a + 1; return a * b + a;"
+expect "2" "const a = 2; { return a; }"
+expect_error "a = 1; static x = a;"
+expect_error "extern ext_value = 1;"
+expect "42" "extern ext_value; extern ext_value; return ext_value;"
+expect "42" "extern const ext_value; { extern const ext_value; return ext_value; }"
+expect_error "extern ext_value; extern const ext_value;"
+expect_error "extern ext_value; extern ext_value = 1;"
+expect_error "static ext_value; extern ext_value;"
+expect_error "ext_value = 1; extern ext_value;"
+expect_error "static x; static x;"
+expect_error "static const k = 5; *&k = 3; return k;"
+expect_error "const k = 5; p = &k; *p = 3;"
+expect_error "const k = 5; *&k += 1;"
+expect "5" "const k = 5; p = &k; return *p;"
+expect "7" "volatile v = 3; p = &v; *p = 7; return v;"
+
+expect "97" "return 'a';"
+expect "10" "return '\n';"
+expect "0" "return '\0';"
+expect "127" "return '\177';"
+expect "65" "return '\x41';"
+expect "39" "return '\'';"
+expect "92" "return '\\\\';"
+expect "27" "return '\e';"
+expect "255" "return '\377';"
+expect "1" "return '\377' == -1;"
+expect "3" "c = 'd' - 'a'; return c;"
+expect_error "return '';"
+expect_error "return 'ab';"
+expect_error "return 'a;"
+expect_error "return '\x';"
+expect_error "return '\x100';"
+expect_error "return '\q';"
+
+expect "1" "return \"abc\" != 0;"
+expect "1" "a = \"abc\"; b = \"abc\"; return a == b;"
+expect "0" "a = \"abc\"; b = \"abd\"; return a == b;"
+expect "1" "a = \"ab\" \"c\"; b = \"abc\"; return a == b;"
+expect "1" "a = \"\"; b = \"\" \"\"; return a == b;"
+expect "0" "a = \"a\n\"; b = \"a\"; return a == b;"
+expect_error "a = \"abc;"
+expect "1" "return *\"abc\" == 97;"
+expect "1" "return *(\"abc\" + 1) == 98;"
+expect "1" "return *(\"a\tb\" + 1) == 9;"
+expect "1" "return *(\"a\0b\" + 1) == 0;"
+expect "1" "return *(\"a\0b\" + 2) == 98;"
+expect "1" "return *(\"ab\" + 2) == 0;"
+expect "1" "return *\"\x41\" == 65;"
+expect "1" "return *\"\377\" == -1;"
+expect "1" "return *\"\\\"\" == 34;"
+expect "1" "return *\"\\\\\" == 92;"
+expect "1" "return *(\"x\" \"\a\" + 1) == 7;"
+expect "1" "s = \"ab\"; return *(s + 1) == 98;"
+
+expect "31" "return 0x1F;"
+expect "31" "return 0X1f;"
+expect "15" "return 017;"
+expect "0" "return 0;"
+expect "5" "return 0b101;"
+expect "10" "return 1'000 / 100;"
+expect "16" "return 10u + 5l + 1ull;"
+expect "3" "return 1LU + 1llu + 1UL;"
+expect "1" "return 0xFFFFFFFFFFFFFFFF == -1;"
+expect "1" "return 4294967296 / 4294967296;"
+expect "1" "return 9223372036854775807 > 0;"
+expect "42" "static x = 0x2a; return x;"
+expect_error "return 08;"
+expect_error "return 0x;"
+expect_error "return 0b2;"
+expect_error "return 18446744073709551616;"
+expect_error "return 9223372036854775808;"
+expect_error "return 1uu;"
+expect_error "return 1lL;"
+expect_error "return 1''0;"
+expect_error "return 1abc;"
+
+expect "3" "// comment
+return 3;"
+expect "6" "a = 2; // a = 5;
+return a * 3;"
+expect "4" "/* a = 5; */ a = 4; return a;"
+expect "7" "a = 1 /* + 2 */ + 6; return a;"
+expect "2" "a = 4 /**/ / 2; return a;"
+expect "8" "/* * / ** */ return 8;"
+expect "9" "return 9; // no trailing newline"
+expect_error "a = 1; /* unterminated"
+expect_error_message "tmp_inc/comment.h:2:1: unterminated block comment" "#include \"tmp_inc/comment.h\""
+
+expect "3" "#define THREE 3
+return THREE;"
+expect "7" "#define A B + 1
+#define B 6
+return A;"
+expect "5" "#define X 2
+#undef X
+X = 5; return X;"
+expect "4" "f = 3;
+#define f f + 1
+return f;"
+expect "6" "#define A B
+#define B A
+A = 6; return A;"
+expect "2" "#
+#define EMPTY
+EMPTY return 2 EMPTY;"
+expect "12" "return N;" -DN=12
+expect "1" "return N;" -DN
+expect "9" "return N;" -D N=9
+expect "8" "N = 8; return N;" -DN=1 -UN
+expect_error "#define 1 2"
+expect_error "#bogus"
+
+expect "7" "#define ADD(a, b) ((a) + (b))
+return ADD(3, 4);"
+expect "12" "#define ADD(a, b) ((a) + (b))
+return ADD(ADD(1, 2), ADD(4,
+  5));"
+expect "9" "#define E() 9
+return E();"
+expect "4" "#define F(x) x
+F = 4; return F;"
+expect "3" "#define F (x)
+x = 3; return F;"
+expect "1" "#define S(x) #x
+a = S(abc); b = \"abc\"; return a == b;"
+expect "1" "#define S(x) #x
+a = S(  a   +  b ); b = \"a + b\"; return a == b;"
+expect "1" "#define S(x) #x
+a = S(\"x\n\"); b = \"\\\"x\\\\n\\\"\"; return a == b;"
+expect "5" "#define CAT(a, b) a ## b
+xy = 5; return CAT(x, y);"
+expect "12" "#define CAT(a, b) a ## b
+return CAT(1, 2);"
+expect "6" "#define CAT(a, b) a ## b
+x = 6; return CAT(x,);"
+expect "1" "#define S(x) #x
+a = S('a'); b = \"'a'\"; return a == b;"
+expect "1" "#define S(x) #x
+a = S('\"'); b = \"'\\\"'\"; return a == b;"
+expect "1" "#define S(x) #x
+a = S(0x1F 10uL 1e+5); b = \"0x1F 10uL 1e+5\"; return a == b;"
+expect "31" "#define C(a, b) a ## b
+return C(0x,1F);"
+expect "97" "#define C(a, b) a ## b
+return C(,'a');"
+expect_error "#define C(a, b) a ## b
+return C(1, .5);"
+expect "2" "#if 0
+return 08 + 0x + 1.5;
+#endif
+return 2;"
+expect_preprocessed "# 1 \"<source>\"
+'a' '\\n' 0x1F 1e+5 .5 10uL" "'a' '\\n' 0x1F 1e+5 .5 10uL"
+expect "3" "#define FIRST(a, ...) a
+return FIRST(3, 4, 5);"
+expect "1" "#define S(...) #__VA_ARGS__
+a = S(1,  2, (3, 4)); b = \"1, 2, (3, 4)\"; return a == b;"
+expect "1" "#define S(...) #__VA_ARGS__
+#define G(f, ...) S(f, ## __VA_ARGS__)
+a = G(x); b = \"x\"; return a == b;"
+expect "1" "#define S(...) #__VA_ARGS__
+#define G(f, ...) S(f, ## __VA_ARGS__)
+a = G(x, y); b = \"x, y\"; return a == b;"
+expect "3" "#define F(a, ...) a + FIRST(0 , ## __VA_ARGS__)
+#define FIRST(a, ...) a
+return F(1, 2, 3) + F(2);"
+expect "3" "#define f(a) a + f
+f = 1; return f(2);"
+expect "7" "h = 7;
+#define g(x) x
+#define h g(h)
+return h;"
+expect "2" "#define id(x) x
+#define one id(1)
+return id(one) + id(id(1));"
+expect "9" "return SQ(3);" "-DSQ(x)=((x)*(x))"
+expect_error "#define ADD(a, b) a + b
+return ADD(1);"
+expect_error "#define ADD(a, b) a + b
+return ADD(1, 2"
+expect_error "#define F(x) #y"
+expect_error "#define F(x) ## x"
+expect_error "#define F(x, x) x"
+expect_error "#define CAT(a, b) a ## b
+return CAT(+, -);"
+
+expect "3" "#include \"tmp_inc/a.h\"
+return FROM_A;"
+expect "6" "#include \"tmp_inc/nested.h\"
+return FROM_NESTED;"
+expect "3" "#include <a.h>
+return FROM_A;" -Itmp_inc
+expect "7" "#include <sys/s.h>
+return SYS;" -I tmp_inc
+expect "3" "#define H \"tmp_inc/a.h\"
+#include H
+return FROM_A;"
+expect "1" "count = 0;
+#include \"tmp_inc/once.h\"
+#include \"tmp_inc/once.h\"
+return count;"
+expect "2" "count = 0;
+#include \"tmp_inc/twice.h\"
+#include \"tmp_inc/twice.h\"
+return count;"
+expect "5" "tmp_main.c"
+expect_error "#include <a.h>"
+expect_error "#include \"missing.h\""
+expect_error "#include \"tmp_inc/self.h\""
+expect_error "#include"
+
+expect "1" "#if 1
+return 1;
+#endif
+return 2;"
+expect "2" "#if 0
+return 1;
+#endif
+return 2;"
+expect "2" "#if 0
+Don't use this; it isn't finished.
+\"unterminated '\q' \"\q\" @ \` \\ x
+#else
+return 2;
+#endif"
+expect_preprocessed "# 1 \"<source>\"
+don't @ \$x \"a\\x41\"" "don't @ \$x \"a\\x41\""
+expect_error "return 'a;"
+expect_error "a = 1; return a @ 2;"
+expect "1" "#define S(x) #x
+a = S(\"a\\x41\"); b = \"\\\"a\\\\x41\\\"\"; return a == b;"
+expect "3" "#if 0
+return 1;
+#elif 1 + 1 == 2
+return 3;
+#else
+return 4;
+#endif"
+expect "4" "#if 2 < 1
+return 1;
+#elif 0
+return 3;
+#else
+return 4;
+#endif"
+expect "5" "#define A 3
+#if A * 2 - 1 == 5
+return 5;
+#endif
+return 0;"
+expect "6" "#ifdef FOO
+return 1;
+#else
+return 6;
+#endif"
+expect "7" "#define FOO
+#ifdef FOO
+return 7;
+#endif
+return 0;"
+expect "8" "#ifndef FOO
+return 8;
+#endif
+return 0;"
+expect "9" "#if defined(FOO) + defined BAR == 1
+return 9;
+#endif
+return 0;" -DBAR
+expect "10" "#if UNDEFINED_NAME == 0
+return 10;
+#endif
+return 0;"
+expect "11" "#if 1
+#if 0
+return 1;
+#elif 1
+return 11;
+#endif
+#else
+return 2;
+#endif"
+expect "12" "#if 0
+#if 1 / 0
+#bogus
+#else
+return 1;
+#endif
+#elif 1
+return 12;
+#endif"
+expect "13" "#define F(x) (x + 1)
+#if F(12) == 13
+return 13;
+#endif
+return 0;"
+expect "1" "count = 0;
+#include \"tmp_inc/guard.h\"
+#include \"tmp_inc/guard.h\"
+return count;"
+expect "2" "count = 0;
+#include \"tmp_inc/guard.h\"
+#undef GUARD_H
+#include \"tmp_inc/guard.h\"
+return count;"
+expect_error "#if 1
+return 1;"
+expect_error "#endif"
+expect_error "#else"
+expect_error "#if 1
+#else
+#else
+#endif"
+expect_error "#if
+#endif"
+expect_error "#if 1 / 0
+#endif"
+expect_error "#if defined
+#endif"
+expect "3" "#define static
+static x = 3; return x;"
+
+expect_preprocessed '# 2 "<source>"
+x = 3;' '#define A 3
+x = A;'
+expect_preprocessed '# 1 "<source>"
+a = 1;
+
+b = "x + 1";' 'a = 1;
+#define S(x) #x
+b = S(x   + 1);'
+expect_preprocessed '# 4 "<source>"
++ + x;
+- -1;
+/ / x;
+< :;' '#define P +
+#define F(x) -x
+#define I(x) x
++P x;
+-F(1);
+/I(/) x;
+<I(:);'
+expect_preprocessed '# 3 "<source>"
+"a b"
+a b' '#define S(x) #x
+#define f(x) x
+S(a
+b) f(a
+b)'
+expect_preprocessed '# 2 "<source>"
+": @\n" "x\y"' '#define str(s) # s
+str(: @\n) str(x\y)'
+expect_preprocessed '# 2 "<source>"
+int j[] = { 123, 45, 67, 89,
+10, 11, 12, };' '#define t(x, y, z) x ## y ## z
+int j[] = { t(1,2,3), t(,4,5), t(6,,7), t(8,9,),
+t(10,,), t(,11,), t(,,12), t(,,) };'
+expect_preprocessed '# 2 "<source>"
+pre 5 pre' '#define b(x, y, z) pre x ## y ## z
+b(,,5) b(,,)'
+expect_preprocessed '# 2 "<source>"
+"\"a\\n\" '"'"'\\'"'"''"'"' '"'"'\"'"'"'"' '#define S(x) #x
+S("a\n" '"'"'\'"'"''"'"' '"'"'"'"'"')'
+expect_preprocessed '# 1 "<source>"
+a;
+# 20 "<source>"
+b;' 'a;
+#if 0
+1
+2
+3
+4
+5
+6
+7
+8
+9
+#endif
+#line 20
+b;'
+expect_preprocessed '# 1 "<source>"
+a;
+# 10 "gen.y"
+b;
+c;' 'a;
+#line 10 "gen.y"
+b;
+c;'
+expect_preprocessed '# 5 "x.y"
+a;' '# 5 "x.y"
+a;'
+expect_preprocessed '# 3 "<source>"
+b;' '/* multi
+line */
+b;'
+expect_preprocessed '# 2 "tmp_main.c"
+return 5;' 'tmp_main.c'
+expect "4" "#line 50 \"gen.y\"
+return 4;"
+expect_error "#line x"
+
+expect_preprocessed '# 1 "<source>"
+a = 1;
+b = 2;' 'a = __LINE__;
+b = __LINE__;'
+expect_preprocessed '# 1 "<source>"
+"<source>" 0 1 2' '__FILE__ __COUNTER__ __COUNTER__ __COUNTER__'
+expect_preprocessed '# 31 "a.c"
+"a.c" 31' '#line 31 "a.c"
+__FILE__ __LINE__'
+SOURCE_DATE_EPOCH=951782400 expect_preprocessed '# 1 "<source>"
+"Feb 29 2000" "00:00:00"' '__DATE__ __TIME__'
+expect_preprocessed '# 1 "<source>"
+1 201112L 1 1 1' '__STDC__ __STDC_VERSION__ __STDC_HOSTED__ __x86_64__ __linux__'
+expect "3" "#if __STDC_VERSION__ >= 201112 + defined __linux__ - 1
+return 3;
+#endif
+return 0;"
+expect "4" "#if __has_include(\"tmp_inc/a.h\") + __has_include(<sys/s.h>) == 2
+return 4;
+#endif
+return 0;" -Itmp_inc
+expect "5" "#if __has_include(<missing.h>)
+return 1;
+#elif defined(__has_include)
+return 5;
+#endif
+return 0;"
+expect "6" "#ifdef __STDC__
+return 1;
+#endif
+return 6;" -U__STDC__
+expect "7" "return __LINE__ + 6;"
+expect_error "__has_include(\"x.h\");"
+expect_error "#error stop here
+return 0;"
+expect "8" "#if 0
+#error not reached
+#endif
+return 8;"
+expect "9" "#warning keep going
+return 9;"
+expect_error_message "<source>:2: error: don't stop here" "return 0;
+#error don't stop here"
+expect_error_message "<source>:1: error: \"unterminated" "#error \"unterminated"
+expect_warning "<source>:1: warning: it's \"fine\"" "#warning it's \"fine\"
+return 9;"
+expect_warning "<source>:3: warning: alignment must be a small power of two, not 3" "#pragma pack(push, 4)
+#pragma pack(pop)
+#pragma pack(3)"
+expect_warning "<source>:1: warning: ignoring #pragma unknown thing" "#pragma unknown thing" -Wunknown-pragmas
+expect "10" "#pragma pack(push, 4)
+#pragma pack(pop)
+#pragma pack(3)
+#pragma unknown thing
+_Pragma(\"pack()\") return 10;" -Wunknown-pragmas
+expect_preprocessed '# 2 "<source>"
+a' '#pragma once
+a'
+expect "11" "a = 5 + \\
+6; return a;"
+expect "12" "#define ADD(x, y) \\
+  ((x) + \\
+   (y))
+return ADD(5, 7);"
+expect "13" "re\\
+turn 13;"
+expect "3" "return sizeof_(\"a\\
+bc\");" -Dsizeof_\(s\)=3
+expect "3" "#define X 1 \\
++ 2
+return X + __LINE__ - 3;"
+expect "14" "%:define X 14
+if (1) <% return X; %> return 0;"
+expect "15" "%:define CAT(a, b) a %:%: b
+CAT(ret, urn) 15;"
+expect "16" "??=define X 16
+if (1) ??< return X; ??> return 0;" -trigraphs
+expect "17" "#define X 17 ??/
++ 0
+return X;" -trigraphs
+expect_error "??=define X 1
+return X;"
+expect_preprocessed '# 1 "<source>"
+"??(" [ ]' '"??(" <: :>'
+expect_preprocessed '# 1 "<source>"
+a->b ++ -- & ~ ! % ^ | && || ? : <<= >>= &= ^= |= *= /= %= += -=' 'a->b ++ -- & ~ ! % ^ | && || ? : <<= >>= &= ^= |= *= /= %= += -='
+expect_preprocessed '# 1 "<source>"
+a +++++ b {:}##' 'a +++++ b <%:%>%:%:'
+expect "7" "return 5- -2;"
+expect_error "return 5--2;"
+expect_preprocessed '# 1 "<source>"
+_Bool _Alignas _Static_assert int sizeof struct _Thread_local' '_Bool _Alignas _Static_assert int sizeof struct _Thread_local'
+expect "18" "#define int
+int a = 18; return a;"
+expect_error "int a = 1; return a;"
+expect "7" "return 1 + 2 * 3;"
+expect "2" "return 10 - 5 - 3;"
+expect "2" "return 17 % 5;"
+expect "40" "return 5 << 3;"
+expect "5" "return 40 >> 3;"
+expect "6" "return 1 + 2 << 1;"
+expect "1" "return 1 << 2 > 3;"
+expect "2" "return 6 & 3;"
+expect "5" "return 6 ^ 3;"
+expect "7" "return 6 | 3;"
+expect "1" "return 1 | 2 ^ 3 & 6;"
+expect "1" "return 1 == 2 < 3;"
+expect "1" "return 2 && 3;"
+expect "0" "return 2 && 0;"
+expect "1" "return 0 || 3;"
+expect "0" "return 0 || 0;"
+expect "1" "return 0 && 1 || 1;"
+expect "0" "a = 0; 0 && (a = 1); 1 || (a = 2); return a;"
+expect "3" "return 1 ? 3 : 4;"
+expect "4" "return 0 ? 3 : 4;"
+expect "6" "return 0 ? 1 : 0 ? 5 : 6;"
+expect "2" "a = 1 ? 2 : 3; return a;"
+expect "3" "a = b = 3; return b;"
+expect "9" "a = 3; a += 6; return a;"
+expect "4" "a = 10; a -= 2; a /= 2; return a;"
+expect "24" "a = 3; a *= 2; a <<= 2; return a;"
+expect "1" "x = 0; c = 0; p = &x; *(c = c + 1, p) += 1; return c;"
+expect "5" "x = 0; c = 0; p = &x; *(c = c + 1, p) += 5; return x;"
+expect "34" "a = 1; b = (a += 2) + 1; return a * 10 + b;"
+expect "1" "a = 9; a %= 4; return a;"
+expect "6" "a = 5; a |= 2; a &= 6; a ^= 0; return a;"
+expect "3" "a = 12; a >>= 2; return a;"
+expect "5" "return (1, 2, 5);"
+expect "5" "for (a = 0, b = 0; a < 5; a = a + 1, b = b + 1) 0; return b;"
+expect "3" "#if 0 && 1 / 0 || 1 ? 1 << 2 == 4 : 0
+return 3;
+#endif
+return 0;"
+expect_error "#if 1 % 0
+#endif
+return 0;"
+expect_error "const a = 1; a += 1; return a;"
+expect "3" "return - -3;"
+expect "3" "return -(1 - 4);"
+expect "5" "return +5;"
+expect "1" "return !0;"
+expect "0" "return !7;"
+expect "1" "return !!3;"
+expect "1" "return ~0 == -1;"
+expect "250" "return ~5 & 255;"
+expect "3" "a = 3; p = &a; return *p;"
+expect "7" "a = 3; p = &a; *p = 7; return a;"
+expect "4" "a = 4; return *&a;"
+expect "1" "a = 0; return &a + 1 - &a;"
+expect "3" "a = 3; b = 5; return *(&b + 1);"
+expect "3" "a = 3; b = 5; return *(1 + &b);"
+expect "5" "a = 3; b = 5; p = &a - 1; return *p;"
+expect "3" "a = 3; b = 5; p = &b; p += 1; return *p;"
+expect "3" "s = \"abc\"; return s + 3 - s;"
+expect_error "1 = 2;"
+expect_error "a = 1; a + 1 = 2;"
+expect_error "a = 1; (a, a) = 2;"
+expect_error "a = 1; b = 2; (a ? a : b) = 3;"
+expect_error "-a = 1;"
+expect_error "a = 1; &a = 0;"
+expect_error "3 += 1;"
+expect "1" "p = &p; return p == &p;"
+expect_error "p = &1;"
+expect_error "a = 1; p = &(a + 1);"
+expect "97" "p = &\"abc\"; return *p;"
+expect "1" "return &\"abc\" == \"abc\";"
+expect "98" "return *(&\"abc\" + 1);"
+expect_error "\"abc\" = 0;"
+expect_error "\"abc\" += 1;"
+expect_error "p = &\"abc\"; &\"abc\" = p;"
+expect "5" "a = 1; p = &a; *(p) += 4; return a;"
+expect "21" "a = 1; b = a++; return a * 10 + b;"
+expect "22" "a = 1; b = ++a; return a * 10 + b;"
+expect "45" "a = 5; b = a--; return a * 10 + b;"
+expect "44" "a = 5; b = --a; return a * 10 + b;"
+expect "98" "p = \"abc\"; p++; return *p;"
+expect "98" "p = \"abc\"; return *++p;"
+expect "99" "p = \"abc\"; q = p++; return *q + p - q + 1;"
+expect "2" "a = 1; p = &a; (*p)++; return a;"
+expect "239" "a = 2; return -a++ * 10 + a;"
+expect "10" "j = 0; for (i = 0; i < 5; i++) j += i; return j;"
+expect_error "return 1++;"
+expect_error "a = 1; a++ ++;"
+expect_error "const c = 1; c++;"
+expect_error "--\"abc\";"
+expect_error "static x = a++;"
+expect "8" "a = 1; return sizeof a;"
+expect "1" "return sizeof *\"abc\";"
+expect "3" "a = 3; b = sizeof a++; return a;"
+expect "16" "x = 2; return sizeof(x) + sizeof -x;"
+expect "1" "return sizeof 1 - 9 > 0;"
+expect "9" "static x = sizeof 1 + 1; return x;"
+
+expect "7" "static x = 1 + 2 * 3; return x;"
+expect "5" "static x = 1 ? 5 : 1 / 0; return x;"
+expect "4" "static x = -16 >> 2; return -x;"
+expect_error "static x = -1 << 3;"
+expect_error "static x = 1 / 0;"
+expect_error "static x = 9223372036854775807 + 1;"
+expect_error "static x = (1, 2);"
+expect_error "static x = \"abc\";"
+expect "1" "#if 0 || 1 ? 1 : 1 / 0
+return 1;
+#endif
+return 0;"
+expect "2" "#if -9223372036854775807 - 1 < 0
+return 2;
+#endif
+return 0;"
+expect_error "#if 9223372036854775807 + 1
+#endif"
+expect_error "#if -9223372036854775807 - 2
+#endif"
+expect_error "#if 1 << 64
+#endif"
+expect_error "#if 1 << -1
+#endif"
+expect_error "#if 1 << 63
+#endif"
+expect "0" "#if -1 < 0u
+return 1;
+#endif
+return 0;"
+expect "1" "#if 0x8000000000000000 > 0 && 18446744073709551615u / 2 > 0
+return 1;
+#endif
+return 0;"
+expect "1" "#if 18446744073709551615u + 1 == 0 && 0u - 1 == -1 && (0u - 1) >> 63 == 1
+return 1;
+#endif
+return 0;"
+expect "1" "#if (1 ? -1 : 0u) > 0 && -1 >> 63 == -1 && 1u << 63 > 0
+return 1;
+#endif
+return 0;"
+expect "1" "static x = 18446744073709551615u + 2; return x;"
+expect "1" "static x = -1 > 0u; return x;"
+expect "1" "static x = 18446744073709551615u / 2 > 0; return x;"
+expect "1" "y = 18446744073709551615u / 2 > 0; return y;"
+expect "1" "return -1 > 0u;"
+expect "0" "return -1 < 1u;"
+expect "1" "a = -1; return a >= 1u;"
+expect "1" "return (1 ? -1 : 0u) > 0;"
+expect "15" "return 18446744073709551615u >> 60;"
+expect "1" "return -8 >> 1u < 0;"
+expect "5" "static x = 18446744073709551615u % 10; return x;"
+expect "5" "return 18446744073709551615u % 10;"
+expect "1" "b = 0u; b = b - 1; return b > 0;"
+expect "1" "b = 0; b -= 1u; return b < 0;"
+expect "42" "extern ext_value; p = &ext_value; return *p;"
+expect "9" "#if !defined(NOPE) && -1 < 0 && ~0 == -1 && +1
+return 9;
+#endif
+return 0;"
+expect "2" "(a = 1) > (a = 2); return a;"
+expect "2" "(a = 1) >= (a = 2); return a;"
+expect "0" "return (a = 1) > (a = 2);"
+expect "1" "return 3 >= 3 > 0;"
+
 echo "OK!"
 
 cleanup
@@ -0,0 +1,62 @@
+use rust_9cc::{
+    lexer::{IntegerType, Lexer},
+    parser::{NodeId, NumberLiteralAST, Parser, UnaryOperationAST, UnaryOperator, AST},
+    preprocess::Preprocessor,
+};
+
+#[test]
+fn number_literals_keep_their_deduced_type() {
+    let tokens = Preprocessor::new().preprocess(Lexer::new(
+        "1; 1u; 2147483648; 0x80000000; 1l; 0xffffffffffffffff; 'a';",
+    ));
+    let mut parser = Parser::new(tokens);
+    let nodes = parser.program();
+    let literals = nodes
+        .iter()
+        .map(|&id| match parser.ast()[id] {
+            AST::NumberLiteral(NumberLiteralAST { value, type_ }) => (value, type_),
+            ref node => panic!("not a number literal: {:?}", node),
+        })
+        .collect::<Vec<(i64, IntegerType)>>();
+    assert_eq!(
+        literals,
+        [
+            (1, IntegerType::Int),
+            (1, IntegerType::UnsignedInt),
+            (2147483648, IntegerType::Long),
+            (0x80000000, IntegerType::UnsignedInt),
+            (1, IntegerType::Long),
+            (-1, IntegerType::UnsignedLong),
+            (97, IntegerType::Int),
+        ]
+    );
+}
+
+#[test]
+fn postfix_operators_bind_tighter_than_prefix_ones() {
+    let tokens = Preprocessor::new().preprocess(Lexer::new("-a++; sizeof --b; ++c--;"));
+    let mut parser = Parser::new(tokens);
+    let nodes = parser.program();
+    let ast = parser.ast();
+    // The chain of unary operators from the outermost one in.
+    let chain = |mut id: NodeId| {
+        let mut ops = Vec::new();
+        while let AST::UnaryOperation(UnaryOperationAST { op, operand }) = ast[id] {
+            ops.push(op);
+            id = operand;
+        }
+        ops
+    };
+    assert_eq!(
+        chain(nodes[0]),
+        [UnaryOperator::Neg, UnaryOperator::PostIncrement]
+    );
+    assert_eq!(
+        chain(nodes[1]),
+        [UnaryOperator::Sizeof, UnaryOperator::PreDecrement]
+    );
+    assert_eq!(
+        chain(nodes[2]),
+        [UnaryOperator::PreIncrement, UnaryOperator::PostDecrement]
+    );
+}
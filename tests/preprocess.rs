@@ -0,0 +1,58 @@
+use rust_9cc::{
+    lexer::Lexer,
+    preprocess::{Pack, PragmaKind, Preprocessor},
+};
+
+#[test]
+fn pragmas_are_recorded_in_source_order() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.preprocess(Lexer::new(
+        "#pragma once
+#pragma pack(push, 4)
+#pragma pack(pop)
+#pragma pack(3)
+#pragma pack()
+_Pragma(\"pack(8)\") a
+#pragma unknown thing
+",
+    ));
+    let pragmas = preprocessor
+        .pragmas()
+        .iter()
+        .map(|p| (p.kind.clone(), p.location.line))
+        .collect::<Vec<(PragmaKind, usize)>>();
+    assert_eq!(
+        pragmas,
+        [
+            (PragmaKind::Once, 1),
+            (PragmaKind::Pack(Pack::Push(Some(4))), 2),
+            (PragmaKind::Pack(Pack::Pop), 3),
+            (PragmaKind::Pack(Pack::Set(None)), 5),
+            (PragmaKind::Pack(Pack::Set(Some(8))), 6),
+        ]
+    );
+}
+
+#[test]
+fn pragma_once_in_an_included_file_is_recorded_once() {
+    let dir = std::env::temp_dir().join(format!("rust_9cc_pragma_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("once.h"), "#pragma once\nx\n").unwrap();
+    let main = dir.join("main.c");
+    std::fs::write(&main, "#include \"once.h\"\n#include \"once.h\"\n").unwrap();
+    let mut preprocessor = Preprocessor::new();
+    let tokens = preprocessor.preprocess_file(&main);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        preprocessor
+            .pragmas()
+            .iter()
+            .map(|p| &p.kind)
+            .collect::<Vec<&PragmaKind>>(),
+        [&PragmaKind::Once]
+    );
+    assert_eq!(
+        tokens.iter().map(|t| t.to_string()).collect::<String>(),
+        "x"
+    );
+}
@@ -0,0 +1,14 @@
+use std::thread;
+
+use rust_9cc::symbol::Symbol;
+
+#[test]
+fn symbols_keep_their_names_across_threads() {
+    let main = Symbol::intern("made_on_main");
+    let (other, seen) = thread::spawn(move || (Symbol::intern("made_on_other"), main.as_str()))
+        .join()
+        .unwrap();
+    assert_eq!(seen, "made_on_main");
+    assert_eq!(other.as_str(), "made_on_other");
+    assert_eq!(Symbol::intern("made_on_other"), other);
+}
@@ -0,0 +1,116 @@
+use rust_9cc::{
+    lexer::{IntegerType, Lexer},
+    parser::{
+        fold_children, walk_block, Ast, BinaryOperator, Folder, NodeId, NumberLiteralAST, Parser,
+        Type, TypeKind, Visitor, AST,
+    },
+    preprocess::Preprocessor,
+    sema::Sema,
+    symbol::Symbol,
+};
+
+// Parses and analyzes a program, so that the side tables are filled.
+fn analyze(source: &str) -> (Ast, Vec<NodeId>) {
+    let mut preprocessor = Preprocessor::new();
+    let tokens = preprocessor.preprocess(Lexer::new(source));
+    let mut parser = Parser::with_locations(tokens, preprocessor.locations());
+    let nodes = parser.program();
+    let mut ast = std::mem::take(parser.ast_mut());
+    Sema::new().analyze(&mut ast, &nodes);
+    (ast, nodes)
+}
+
+#[derive(Default)]
+struct IdentifierCounter {
+    identifiers: Vec<Symbol>,
+    blocks: usize,
+}
+
+impl Visitor for IdentifierCounter {
+    fn visit_identifier(&mut self, _ast: &Ast, _id: NodeId, name: Symbol) {
+        self.identifiers.push(name);
+    }
+    fn visit_block(&mut self, ast: &Ast, _id: NodeId, nodes: &[NodeId]) {
+        self.blocks += 1;
+        walk_block(self, ast, nodes);
+    }
+}
+
+#[test]
+fn visitor_reaches_every_identifier() {
+    let (ast, nodes) = analyze("a = 1; { const b = a + 2; { a += *&b; } } return a ? b : -a;");
+    let mut counter = IdentifierCounter::default();
+    for node in nodes {
+        counter.visit(&ast, node);
+    }
+    assert_eq!(
+        counter
+            .identifiers
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>(),
+        ["a", "a", "a", "b", "a", "b", "a"]
+    );
+    assert_eq!(counter.blocks, 2);
+}
+
+// Rewrites `x + 0` to `x`, and adds up two literals into a new node.
+struct Simplifier;
+
+impl Folder for Simplifier {
+    fn fold_binary_operation(&mut self, ast: &mut Ast, id: NodeId) -> NodeId {
+        let id = fold_children(self, ast, id);
+        let AST::BinaryOperation(node) = &ast[id] else {
+            unreachable!();
+        };
+        if node.op != BinaryOperator::Add {
+            return id;
+        }
+        match (&ast[node.lhs], &ast[node.rhs]) {
+            (_, AST::NumberLiteral(NumberLiteralAST { value: 0, .. })) => node.lhs,
+            (AST::NumberLiteral(lhs), AST::NumberLiteral(rhs)) => {
+                ast.push(AST::NumberLiteral(NumberLiteralAST {
+                    value: lhs.value + rhs.value,
+                    type_: IntegerType::Int,
+                }))
+            }
+            _ => id,
+        }
+    }
+}
+
+#[test]
+fn folder_rewrites_nodes_and_keeps_side_tables_of_kept_nodes() {
+    let (mut ast, nodes) = analyze("x = 5;\nreturn x + 0;\nreturn 1 + 2;");
+    let nodes = nodes
+        .into_iter()
+        .map(|node| Simplifier.fold(&mut ast, node))
+        .collect::<Vec<NodeId>>();
+
+    // `x + 0` became the identifier node itself, which keeps what sema and the parser
+    // recorded about it.
+    let AST::Return(x) = ast[nodes[1]] else {
+        panic!("not a return: {:?}", ast[nodes[1]]);
+    };
+    assert_eq!(ast[x], AST::Identifier(Symbol::intern("x")));
+    assert_eq!(ast.spans.get(x).map(|l| l.line), Some(2));
+    assert_eq!(ast.types.get(x), Some(&Type::new(TypeKind::Long)));
+    assert_eq!(ast.symbols.get(x).map(|v| v.name().as_str()), Some("x"));
+
+    // `1 + 2` became a node the folder pushed, which has no entries yet.
+    let AST::Return(sum) = ast[nodes[2]] else {
+        panic!("not a return: {:?}", ast[nodes[2]]);
+    };
+    assert_eq!(
+        ast[sum],
+        AST::NumberLiteral(NumberLiteralAST {
+            value: 3,
+            type_: IntegerType::Int,
+        })
+    );
+    assert_eq!(ast.spans.get(sum), None);
+    assert_eq!(ast.types.get(sum), None);
+
+    // The return statements themselves were kept in place.
+    assert_eq!(ast.spans.get(nodes[2]).map(|l| l.line), Some(3));
+}